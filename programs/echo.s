@@ -0,0 +1,45 @@
+.globl _start
+
+.data
+
+stdio:
+    .ascii "stdio"
+stdio_len = . - stdio
+
+newline:
+    .ascii "\n"
+
+.text
+
+// Writes each argument after the program name on its own line
+_start:
+    ldr x19, [sp] // argc
+    add x20, sp, #8 // argv
+    ldr x0, =stdio
+    mov x1, stdio_len
+    svc #6 // Open
+    mov x21, x0
+    mov x22, #1
+
+1:  cmp x22, x19
+    b.ge 4f
+    ldr x23, [x20, x22, lsl #3]
+    mov x2, xzr
+
+2:  ldrb w3, [x23, x2] // strlen
+    cbz w3, 3f
+    add x2, x2, #1
+    b 2b
+
+3:  mov x0, x21
+    mov x1, x23
+    svc #9 // Write
+    mov x0, x21
+    ldr x1, =newline
+    mov x2, #1
+    svc #9 // Write
+    add x22, x22, #1
+    b 1b
+
+4:  mov x0, #0
+    svc #2 // Exit
@@ -0,0 +1,41 @@
+.globl _start
+
+.data
+
+missing:
+    .ascii "file:USERS./MOE./MISSING.ELF"
+missing_len = . - missing
+
+echo:
+    .ascii "file:USERS./MOE./ECHO.ELF"
+echo_len = . - echo
+
+echo_name:
+    .asciz "echo.elf"
+hello:
+    .asciz "Hello"
+world:
+    .asciz "World"
+
+.balign 8
+argv:
+    .quad echo_name
+    .quad hello
+    .quad world
+    .quad 0
+
+.text
+
+_start:
+    ldr x0, =missing
+    mov x1, missing_len
+    mov x2, xzr
+    mov x3, xzr
+    svc #10 // Exec, returns 1 since the file does not exist
+    ldr x0, =echo
+    mov x1, echo_len
+    ldr x2, =argv
+    mov x3, xzr
+    svc #10 // Exec, does not return
+    mov x0, 1
+    svc #2 // Exit
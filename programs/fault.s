@@ -0,0 +1,9 @@
+.global _start
+
+.text
+
+// Loads from an unmapped address, the kernel should kill only this thread
+_start:
+    mov x0, #0
+    ldr x1, [x0]
+    svc 2
@@ -0,0 +1,69 @@
+.globl _start
+
+.data
+
+stdio:
+    .ascii "stdio"
+stdio_len = . - stdio
+
+msg:
+    .ascii "Hello through a pipe\n"
+msg_len = . - msg
+
+.balign 8
+handles:
+    .quad 0
+    .quad 0
+
+buffer:
+    .space 64
+buffer_len = . - buffer
+
+.text
+
+// Sends a message through a pipe and writes what comes out of it to stdio. Exits with 0 if the
+// message arrived, the end of the stream was seen after closing the write end and the kernel
+// refused to store the handles at a kernel address.
+_start:
+    movz x0, #0xFFFF, lsl #48
+    svc #17 // Pipe, fails for a kernel address
+    cmn x0, #1
+    b.ne 1f
+
+    ldr x19, =handles
+    mov x0, x19
+    svc #17 // Pipe
+    cbnz x0, 1f
+
+    ldr x0, [x19, #8]
+    ldr x1, =msg
+    mov x2, msg_len
+    svc #9 // Write
+    ldr x0, [x19, #8]
+    svc #7 // Close the write end
+    ldr x0, [x19]
+    ldr x1, =buffer
+    mov x2, buffer_len
+    svc #8 // Read
+    mov x1, msg_len
+    cmp x0, x1
+    b.ne 1f
+
+    ldr x0, =stdio
+    mov x1, stdio_len
+    svc #6 // Open
+    ldr x1, =buffer
+    mov x2, msg_len
+    svc #9 // Write
+
+    ldr x0, [x19]
+    ldr x1, =buffer
+    mov x2, buffer_len
+    svc #8 // Read, returns 0 at the end of the stream
+    cbnz x0, 1f
+
+    mov x0, #0
+    svc #2 // Exit
+
+1:  mov x0, #1
+    svc #2 // Exit
@@ -1,4 +1,6 @@
+pub mod backtrace;
 pub mod cpu;
+pub mod generic_timer;
 #[macro_use]
 pub mod registers;
 pub mod interrupt;
@@ -0,0 +1,243 @@
+//! Frame pointer based stack unwinding and symbolization of kernel addresses
+//!
+//! The kernel is built with frame pointers, so x29 points to a record of the caller's frame pointer
+//! followed by the return address. The loaded kernel image is stripped, so the symbols are read
+//! from a copy of the kernel ELF on the boot partition.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::asm;
+
+use crate::{
+    aarch64::mmu::KERNEL_ADDRESS_START,
+    elf::{ELF64Header, SectionHeader, SectionType, StringTable, SymbolTable, SymbolType},
+    println,
+    utils::demangle::demangle,
+};
+
+/// Upper bound on the number of frames printed, in case the frame records form a cycle
+const MAX_FRAMES: usize = 32;
+
+/// Number of symbols read from the file at a time
+const SYMBOLS_PER_READ: usize = 170;
+
+struct KernelSymbol {
+    address: u64,
+    size: u64,
+    name_start: usize,
+    name_end: usize,
+}
+
+/// The function symbols of the kernel, sorted by address, with demangled names
+pub struct KernelSymbols {
+    symbols: Vec<KernelSymbol>,
+    names: String,
+}
+
+impl KernelSymbols {
+    /// Reads the function symbols from an ELF file. read_at reads from a byte offset of the file
+    /// and returns the number of bytes read.
+    pub fn from_elf(read_at: impl Fn(usize, &mut [u8]) -> usize) -> Result<Self, &'static str> {
+        let read = |offset: u64, size: u64| {
+            let mut buffer = vec![0; size as usize];
+
+            if read_at(offset as usize, &mut buffer) == buffer.len() {
+                Ok(buffer)
+            } else {
+                Err("Unexpected end of file")
+            }
+        };
+
+        let header_buffer = read(0, core::mem::size_of::<ELF64Header>() as u64)?;
+        let header = ELF64Header::try_from(&header_buffer[..])?;
+
+        let entry_size = header.section_header_entry_size as usize;
+
+        if entry_size < SectionHeader::SIZE {
+            return Err("Section header entries are too small");
+        }
+
+        let section_table = read(
+            header.section_header_offset,
+            (entry_size * header.section_header_entry_num as usize) as u64,
+        )?;
+
+        let sections = section_table
+            .chunks_exact(entry_size)
+            .map(SectionHeader::try_from)
+            .collect::<Result<Vec<SectionHeader>, _>>()?;
+
+        let symbol_section = sections
+            .iter()
+            .find(|section| section.section_type == SectionType::SymbolTable)
+            .ok_or("No symbol table")?;
+
+        let string_section = sections
+            .get(symbol_section.link as usize)
+            .ok_or("No string table for the symbol table")?;
+
+        let strings = read(string_section.offset, string_section.size)?;
+
+        let symbol_size = (symbol_section.entry_size as usize).max(1);
+        let number_of_symbols = symbol_section.size / symbol_size as u64;
+
+        let mut symbols = Vec::new();
+        let mut names = String::new();
+
+        // The table is read in chunks, so only the string table has to fit in memory at once
+        for first in (0..number_of_symbols).step_by(SYMBOLS_PER_READ) {
+            let count = (number_of_symbols - first).min(SYMBOLS_PER_READ as u64);
+            let data = read(
+                symbol_section.offset + first * symbol_size as u64,
+                count * symbol_size as u64,
+            )?;
+
+            let table = SymbolTable::new(&data, symbol_size, StringTable::new(&strings));
+
+            for (symbol, name) in table.iter() {
+                let name = match name {
+                    Some(name) => name,
+                    None => continue,
+                };
+
+                if symbol.symbol_type() != SymbolType::Function
+                    || !symbol.is_defined()
+                    || symbol.size == 0
+                {
+                    continue;
+                }
+
+                let name_start = names.len();
+
+                names.push_str(&demangle(name));
+
+                symbols.push(KernelSymbol {
+                    address: symbol.value,
+                    size: symbol.size,
+                    name_start,
+                    name_end: names.len(),
+                });
+            }
+        }
+
+        symbols.sort_unstable_by_key(|symbol| symbol.address);
+        symbols.shrink_to_fit();
+        names.shrink_to_fit();
+
+        Ok(Self { symbols, names })
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Returns the name of the function containing the address and the offset into it
+    pub fn resolve(&self, address: u64) -> Option<(&str, u64)> {
+        let index = self
+            .symbols
+            .partition_point(|symbol| symbol.address <= address)
+            .checked_sub(1)?;
+
+        let symbol = &self.symbols[index];
+        let offset = address - symbol.address;
+
+        if offset >= symbol.size {
+            return None;
+        }
+
+        Some((&self.names[symbol.name_start..symbol.name_end], offset))
+    }
+}
+
+/// Iterates over the return addresses of a chain of frame records, innermost first
+pub struct StackFrames {
+    frame_pointer: u64,
+    remaining: usize,
+}
+
+impl StackFrames {
+    pub fn from_frame_pointer(frame_pointer: u64) -> Self {
+        Self {
+            frame_pointer,
+            remaining: MAX_FRAMES,
+        }
+    }
+}
+
+impl Iterator for StackFrames {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.remaining == 0
+            || self.frame_pointer < KERNEL_ADDRESS_START
+            || !self.frame_pointer.is_multiple_of(16)
+        {
+            return None;
+        }
+
+        let record = self.frame_pointer as *const u64;
+        let (previous, return_address) = unsafe { (*record, *record.add(1)) };
+
+        // The stack grows down, so the records of callers lie at higher addresses
+        if previous <= self.frame_pointer {
+            self.remaining = 0;
+        } else {
+            self.frame_pointer = previous;
+            self.remaining -= 1;
+        }
+
+        if return_address == 0 {
+            None
+        } else {
+            Some(return_address)
+        }
+    }
+}
+
+/// The frame pointer of the function this is inlined into
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let frame_pointer: u64;
+
+    unsafe {
+        asm!("mov {}, x29", out(reg) frame_pointer);
+    }
+
+    frame_pointer
+}
+
+/// Prints an address with its symbol. Frames are numbered, the program counter has no index.
+fn print_address(index: Option<usize>, address: u64, symbols: Option<&KernelSymbols>) {
+    match index {
+        Some(index) => crate::print!("  #{:<2} {:#x}", index, address),
+        None => crate::print!("  pc  {:#x}", address),
+    }
+
+    if let Some((name, offset)) = symbols.and_then(|symbols| symbols.resolve(address)) {
+        crate::print!(" {}+{:#x}", name, offset);
+    }
+
+    println!("");
+}
+
+/// Prints the program counter, if known, followed by the return addresses of the frame chain
+/// starting at frame_pointer. Nothing is allocated, so this is safe to use after an allocation
+/// failure.
+pub fn print_backtrace(
+    program_counter: Option<u64>,
+    frame_pointer: u64,
+    symbols: Option<&KernelSymbols>,
+) {
+    println!("Backtrace:");
+
+    if let Some(program_counter) = program_counter {
+        print_address(None, program_counter, symbols);
+    }
+
+    for (i, return_address) in StackFrames::from_frame_pointer(frame_pointer).enumerate() {
+        // The return address is the instruction after the call, which may belong to the next
+        // function if the call never returns
+        print_address(Some(i), return_address - 4, symbols);
+    }
+}
@@ -1,8 +1,14 @@
 use alloc::boxed::Box;
 use alloc::string::String;
+use alloc::vec::Vec;
 use core::arch::asm;
+use core::sync::atomic::AtomicU64;
 
-use crate::{aarch64::syscall::Syscall, read, write};
+use crate::{
+    aarch64::syscall::Syscall,
+    platform::{kernel_object::InheritedHandles, thread::ThreadInfo},
+    read, write,
+};
 
 /// Returns the id of the cpu core as reported by the arm MPIDR_EL1 system register
 #[allow(dead_code)]
@@ -31,6 +37,13 @@ pub fn wait_for_cycles(cycles: u64) {
     }
 }
 
+/// Waits in a low power state until an interrupt is pending
+pub fn wait_for_interrupt() {
+    unsafe {
+        asm!("wfi");
+    }
+}
+
 pub fn eret() {
     unsafe {
         asm!("eret");
@@ -49,14 +62,79 @@ pub fn data_buffer() {
     }
 }
 
+/// Smallest data cache line size of the core in bytes
+pub fn data_cache_line_size() -> usize {
+    // DminLine, log2 of the number of words in the line
+    4 << ((read!("ctr_el0") >> 16) & 0xF)
+}
+
+/// Smallest instruction cache line size of the core in bytes
+pub fn instruction_cache_line_size() -> usize {
+    // IminLine, log2 of the number of words in the line
+    4 << (read!("ctr_el0") & 0xF)
+}
+
+/// Makes instructions written to the range through the data caches visible to instruction fetches
+pub fn synchronize_instruction_cache(start: usize, length: usize) {
+    let end = start + length;
+    let data_line_size = data_cache_line_size();
+    let instruction_line_size = instruction_cache_line_size();
+
+    for line in (start & !(data_line_size - 1)..end).step_by(data_line_size) {
+        unsafe {
+            asm!("dc cvau, {}", in(reg) line);
+        }
+    }
+
+    unsafe {
+        asm!("dsb ish");
+    }
+
+    for line in (start & !(instruction_line_size - 1)..end).step_by(instruction_line_size) {
+        unsafe {
+            asm!("ic ivau, {}", in(reg) line);
+        }
+    }
+
+    unsafe {
+        asm!("dsb ish", "isb");
+    }
+}
+
+/// Writes the cache lines covering the range back to memory and drops them from the caches, so
+/// that devices reading memory directly see the writes and the core sees the writes of devices
+pub fn clean_and_invalidate_data_cache(start: usize, length: usize) {
+    let line_size = data_cache_line_size();
+    let end = start + length;
+
+    for line in (start & !(line_size - 1)..end).step_by(line_size) {
+        unsafe {
+            asm!("dc civac, {}", in(reg) line);
+        }
+    }
+
+    data_buffer();
+}
+
 pub fn create_thread<T>(function: extern "C" fn(arg: T) -> (), name: String, arg: usize) -> u64 {
-    start_thread(function, &name, arg)
+    start_thread(function, &name, arg, &InheritedHandles::None)
+}
+
+/// Creates a thread that starts with some or all of the calling thread's handles
+pub fn create_thread_with_handles<T>(
+    function: extern "C" fn(arg: T) -> (),
+    name: String,
+    arg: usize,
+    handles: InheritedHandles,
+) -> u64 {
+    start_thread(function, &name, arg, &handles)
 }
 
 pub extern "C" fn start_thread<T>(
     _function: extern "C" fn(arg: T) -> (),
     _name: *const String,
     _arg: usize,
+    _handles: *const InheritedHandles,
 ) -> u64 {
     unsafe {
         asm!("svc {}", const Syscall::Thread as usize);
@@ -182,3 +260,236 @@ pub fn write_object(handle: u64, buffer: &[u8]) -> usize {
 
     bytes_written
 }
+
+/// Adds another handle to the object of the handle. Both have to be closed for the object to be
+/// closed. Returns the new handle, or 0 if the handle does not exist.
+pub extern "C" fn duplicate_object(_handle: u64) -> u64 {
+    unsafe {
+        asm!("svc {}", const Syscall::Dup as usize);
+    }
+
+    let new_handle: u64;
+
+    unsafe {
+        asm!("mov {}, x0", out(reg) new_handle);
+    }
+
+    new_handle
+}
+
+/// Creates a pipe and returns the handles of its read end and write end, or None if it could not
+/// be created
+pub fn pipe() -> Option<(u64, u64)> {
+    let mut handles = [0u64; 2];
+    let result: u64;
+
+    unsafe {
+        asm!("mov x0, {}", in(reg) handles.as_mut_ptr());
+
+        asm!("svc {}", const Syscall::Pipe as usize);
+
+        asm!("mov {}, x0", out(reg) result);
+    }
+
+    if result == 0 {
+        Some((handles[0], handles[1]))
+    } else {
+        None
+    }
+}
+
+/// Replaces the calling thread's image with the given program, without arguments. Only returns
+/// on failure, in which case the error code is returned.
+pub fn exec(program: &str) -> u64 {
+    let error_code: u64;
+
+    unsafe {
+        asm!("
+            mov x0, {}
+            mov x1, {}
+            mov x2, xzr
+            mov x3, xzr
+        ",
+            in(reg) program.as_ptr(),
+            in(reg) program.len()
+        );
+
+        asm!("svc {}", const Syscall::Exec as usize);
+
+        asm!("mov {}, x0", out(reg) error_code);
+    }
+
+    error_code
+}
+
+/// Returns the priority of the thread, where 0 is the calling thread
+pub extern "C" fn get_priority(_thread_id: u64) -> u64 {
+    unsafe {
+        asm!("svc {}", const Syscall::GetPriority as usize);
+    }
+
+    let priority: u64;
+
+    unsafe {
+        asm!("mov {}, x0", out(reg) priority);
+    }
+
+    priority
+}
+
+/// Sets the priority of the calling thread (0) or one of its children and returns the previous one
+pub extern "C" fn set_priority(_thread_id: u64, _priority: u64) -> u64 {
+    unsafe {
+        asm!("svc {}", const Syscall::SetPriority as usize);
+    }
+
+    let previous: u64;
+
+    unsafe {
+        asm!("mov {}, x0", out(reg) previous);
+    }
+
+    previous
+}
+
+/// Ends a descendant of the calling thread and its own descendants with the given exit code.
+/// Returns 0, or u64::MAX if the thread does not exist or is not a descendant.
+pub extern "C" fn kill_thread(_thread_id: u64, _code: u64) -> u64 {
+    unsafe {
+        asm!("svc {}", const Syscall::Kill as usize);
+    }
+
+    let result: u64;
+
+    unsafe {
+        asm!("mov {}, x0", out(reg) result);
+    }
+
+    result
+}
+
+/// Sleeps until woken by [futex_wake] if the word holds the expected value. Returns 0 when woken
+/// and 1 if the value differed.
+pub extern "C" fn futex_wait(_word: &AtomicU64, _expected: u64) -> u64 {
+    unsafe {
+        asm!("svc {}", const Syscall::FutexWait as usize);
+    }
+
+    let result: u64;
+
+    unsafe {
+        asm!("mov {}, x0", out(reg) result);
+    }
+
+    result
+}
+
+/// Wakes up to count threads sleeping on the word and returns the number woken
+pub extern "C" fn futex_wake(_word: &AtomicU64, _count: usize) -> u64 {
+    unsafe {
+        asm!("svc {}", const Syscall::FutexWake as usize);
+    }
+
+    let woken: u64;
+
+    unsafe {
+        asm!("mov {}, x0", out(reg) woken);
+    }
+
+    woken
+}
+
+/// Moves the end of the heap to the address and returns the new end. The end stays where it is if
+/// the address lies outside of the heap or the memory runs out, so 0 returns the current end.
+pub extern "C" fn brk(_address: u64) -> u64 {
+    unsafe {
+        asm!("svc {}", const Syscall::Brk as usize);
+    }
+
+    let program_break: u64;
+
+    unsafe {
+        asm!("mov {}, x0", out(reg) program_break);
+    }
+
+    program_break
+}
+
+/// Grows or shrinks the heap by the increment and returns the previous end of the heap, or None
+/// if it could not be moved
+pub fn sbrk(increment: i64) -> Option<u64> {
+    let previous = brk(0);
+    let requested = previous.checked_add_signed(increment)?;
+
+    if brk(requested) == requested {
+        Some(previous)
+    } else {
+        None
+    }
+}
+
+/// Maps length bytes of zeroed pages at the page aligned address, or wherever there is room if the
+/// address is 0. The protection combines PROT_WRITE and PROT_EXEC from user_memory. Returns the
+/// address of the pages, or u64::MAX if the range is invalid, already mapped or the memory runs
+/// out.
+pub extern "C" fn mmap(_address: u64, _length: u64, _protection: u64) -> u64 {
+    unsafe {
+        asm!("svc {}", const Syscall::Mmap as usize);
+    }
+
+    let address: u64;
+
+    unsafe {
+        asm!("mov {}, x0", out(reg) address);
+    }
+
+    address
+}
+
+/// Unmaps and frees pages mapped with [mmap]. Returns 0, or u64::MAX if the range lies outside of
+/// the part of the address space used by mmap.
+pub extern "C" fn munmap(_address: u64, _length: u64) -> u64 {
+    unsafe {
+        asm!("svc {}", const Syscall::Munmap as usize);
+    }
+
+    let result: u64;
+
+    unsafe {
+        asm!("mov {}, x0", out(reg) result);
+    }
+
+    result
+}
+
+/// Returns a snapshot of all threads, including the idle thread of every core
+pub fn list_threads() -> Vec<ThreadInfo> {
+    let mut threads: Vec<ThreadInfo> = Vec::new();
+
+    loop {
+        let count: usize;
+
+        unsafe {
+            asm!("
+                mov x0, {}
+                mov x1, {}
+            ",
+                in(reg) threads.as_mut_ptr(),
+                in(reg) threads.capacity()
+            );
+
+            asm!("svc {}", const Syscall::ListThreads as usize);
+
+            asm!("mov {}, x0", out(reg) count);
+        }
+
+        // More threads may have been started in the meantime, so this is checked again
+        if count <= threads.capacity() {
+            unsafe { threads.set_len(count) };
+
+            return threads;
+        }
+
+        threads.reserve_exact(count);
+    }
+}
@@ -0,0 +1,27 @@
+//! The physical generic timer of the calling core
+//!
+//! Every core has its own timer, so it can preempt threads on cores that do not receive the
+//! interrupts of the system timer. EL1 access to it is enabled in cnthctl_el2 by start.s.
+
+use core::arch::asm;
+
+use crate::{read, write};
+
+/// Ticks per second of the system counter
+pub fn frequency() -> u64 {
+    read!("cntfrq_el0") as u64
+}
+
+/// Raises the timer interrupt of the core after the given number of microseconds. Setting a new
+/// timeout also clears a pending one.
+pub fn set_timeout(micros: u64) {
+    let ticks = frequency() * micros / 1_000_000;
+
+    write!("cntp_tval_el0", ticks);
+    write!("cntp_ctl_el0", 1u64); // Enabled and not masked
+}
+
+/// Stops the timer, so that it no longer interrupts the core
+pub fn disable() {
+    write!("cntp_ctl_el0", 0u64);
+}
@@ -2,8 +2,10 @@ use core::{
     arch::asm,
     cell::UnsafeCell,
     ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
+use crate::aarch64::cpu;
 use crate::platform::interrupt::IRQSource;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -65,22 +67,52 @@ pub fn pop_irq_state() -> InterruptState {
     }
 }
 
+/// The low byte of the lock word holds the owning core + 1, the nesting depth is counted above it
+const OWNER_MASK: usize = 0xff;
+const NESTING_UNIT: usize = 1 << 8;
+
+/// A lock that masks interrupts on the holding core and spins while another core holds it. The
+/// holding core may lock it again, as the kernel calls back into the platform while locked.
 #[derive(Debug)]
 pub struct IRQLock<T> {
+    state: AtomicUsize,
     data: UnsafeCell<T>,
 }
 
 impl<'a, T> IRQLock<T> {
     pub const fn new(data: T) -> Self {
         Self {
+            state: AtomicUsize::new(0),
             data: UnsafeCell::new(data),
         }
     }
 
     pub fn lock(&'a self) -> IRQLockGuard<'a, T> {
-        // TODO: do we need to make sure that the irq state is atomically popped?
+        let irq_state = pop_irq_state();
+        let owner = cpu::core_id() + 1;
+        let held = self.state.load(Ordering::Relaxed);
+
+        // Only the owner ever stores its own id, so this cannot change under our feet
+        if held & OWNER_MASK == owner {
+            self.state.store(held + NESTING_UNIT, Ordering::Relaxed);
+        } else {
+            while self
+                .state
+                .compare_exchange_weak(
+                    0,
+                    owner + NESTING_UNIT,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+            {
+                core::hint::spin_loop();
+            }
+        }
+
         IRQLockGuard {
-            state: pop_irq_state(),
+            state: irq_state,
+            lock: &self.state,
             data: unsafe { &mut *self.data.get() },
         }
     }
@@ -94,11 +126,27 @@ impl<'a, T> IRQLock<T> {
     }
 }
 
+unsafe impl<T> Sync for IRQLock<T> {}
+
 pub struct IRQLockGuard<'a, T> {
     state: InterruptState,
+    lock: &'a AtomicUsize,
     data: &'a mut T,
 }
 
+impl<'a, T> IRQLockGuard<'a, T> {
+    /// Keeps the lock held without restoring the interrupt state and returns the lock word.
+    /// Storing 0 to it releases the lock at any depth, which lets code that switches stacks and
+    /// never returns release the lock once it has left the old stack.
+    pub fn leak(guard: Self) -> &'a AtomicUsize {
+        let lock = guard.lock;
+
+        core::mem::forget(guard);
+
+        lock
+    }
+}
+
 impl<'a, T> Deref for IRQLockGuard<'a, T> {
     type Target = T;
 
@@ -115,6 +163,14 @@ impl<'a, T> DerefMut for IRQLockGuard<'a, T> {
 
 impl<'a, T> Drop for IRQLockGuard<'a, T> {
     fn drop(&mut self) {
+        let held = self.lock.load(Ordering::Relaxed);
+
+        if held < 2 * NESTING_UNIT {
+            self.lock.store(0, Ordering::Release);
+        } else {
+            self.lock.store(held - NESTING_UNIT, Ordering::Relaxed);
+        }
+
         set_irq_state(self.state);
     }
 }
@@ -1,11 +1,12 @@
-use super::registers::{
-    SystemControlRegister, TranslationControlRegister, UserTranslationTableBaseRegister,
-};
-use crate::aarch64::cpu;
+use super::registers::{SystemControlRegister, UserTranslationTableBaseRegister};
 use crate::aarch64::registers::KernelTranslationTableBaseRegister;
-use crate::bitfield;
+use crate::{bitfield, read};
 use core::arch::asm;
 
+/// Kernel addresses are mapped through ttbr1 in the upper half of the address space, the same for
+/// every thread
+pub const KERNEL_ADDRESS_START: u64 = 0xFFFF_0000_0000_0000;
+
 pub fn get_user_table() -> usize {
     UserTranslationTableBaseRegister::read_to_buffer().value()
 }
@@ -14,62 +15,106 @@ pub fn get_kernel_table() -> usize {
     KernelTranslationTableBaseRegister::read_to_buffer().value()
 }
 
-pub unsafe fn init(table_start: *mut usize) {
-    let table = core::slice::from_raw_parts_mut(table_start, 512);
+/// Invalidates all stage 1 EL1&0 translations so that changes to the page tables take effect
+pub fn invalidate_tlb() {
+    unsafe {
+        asm!("dsb ishst", "tlbi vmalle1is", "dsb ish", "isb");
+    }
+}
 
-    let mair = 0;
+/// Attribute index in mair_el1 of the Device-nGnRnE memory the peripherals are mapped as
+pub const DEVICE_ATTRIBUTE_INDEX: usize = 0;
+/// Attribute index in mair_el1 of the write-back cacheable normal memory RAM is mapped as
+pub const NORMAL_ATTRIBUTE_INDEX: usize = 1;
 
-    UserTranslationTableBaseRegister::read_to_buffer()
-        .set_table_pointer(table_start as usize)
-        .write_to_register();
+/// Device-nGnRnE, no gathering, reordering or early write acknowledgement
+pub const DEVICE_ATTRIBUTES: u64 = 0x00;
+/// Normal memory, inner and outer write-back with read and write allocation
+pub const NORMAL_ATTRIBUTES: u64 = 0xFF;
 
-    write!("mair_el1", mair);
+/// Shareability of RAM, so that the caches of all cores stay coherent
+pub const INNER_SHAREABLE: u64 = 0b11;
 
-    TranslationControlRegister::read_to_buffer()
-        .set_granule_size(TranslationControlRegister::GranuleSize::Kb4 as usize)
-        .set_table_offset(33)
-        .write_to_register();
+/// Result of translating a virtual address with the current tables
+struct Translation {
+    physical_address: u64,
+    attributes: u64,
+    shareability: u64,
+}
 
-    cpu::instruction_buffer();
+/// Translates the address for a read at EL1 like a load would, without faulting
+fn translate(address: usize) -> Option<Translation> {
+    let par: u64;
 
-    let attributes = MemoryAttributes::new()
-        .set_access_flag(1)
-        .set_entry_type(MemoryAttributes::BLOCK_ENTRY);
+    unsafe {
+        asm!("at s1e1r, {}", "isb", "mrs {}, par_el1", in(reg) address, out(reg) par);
+    }
 
-    for i in 0..512 {
-        table[i] = attributes.clone().set_address(i << 21).value;
+    // F, the translation faulted
+    if par & 1 == 1 {
+        return None;
     }
 
-    cpu::data_buffer();
+    Some(Translation {
+        physical_address: (par & 0x0000_FFFF_FFFF_F000) | (address as u64 & 0xFFF),
+        attributes: par >> 56,
+        shareability: (par >> 7) & 0b11,
+    })
+}
 
-    SystemControlRegister::read_to_buffer()
-        .set_translation_state(SystemControlRegister::TranslationState::Enabled as usize)
-        .set_cache_enable(1)
-        .write_to_register();
+/// Checks the translation start.s set up before the kernel depends on it: translation and caches
+/// are on, the user table is in ttbr0, the kernel maps RAM as cacheable normal memory at the same
+/// physical addresses as the identity map and the peripherals at `device_address` as device
+/// memory.
+pub fn self_check(user_table: usize, device_address: usize) -> Result<(), &'static str> {
+    let control = SystemControlRegister::read_to_buffer();
 
-    cpu::instruction_buffer();
-}
+    if control.get_translation_state() != SystemControlRegister::TranslationState::Enabled as usize
+    {
+        return Err("translation is disabled");
+    }
 
-pub unsafe fn init_tested(table_start: *mut usize) -> Result<(), ()> {
-    for i in (0..0x60_000).step_by(0x1000) {
-        core::ptr::write_volatile(i as *mut usize, i);
+    if control.get_cache_enable() != 1 || control.get_instruction_cache_enable() != 1 {
+        return Err("caches are disabled");
     }
 
-    for i in (0x256_000..0x20000000).step_by(0x8000) {
-        core::ptr::write_volatile(i as *mut usize, i);
+    let mair = read!("mair_el1") as u64;
+
+    if (mair >> (8 * DEVICE_ATTRIBUTE_INDEX)) & 0xFF != DEVICE_ATTRIBUTES
+        || (mair >> (8 * NORMAL_ATTRIBUTE_INDEX)) & 0xFF != NORMAL_ATTRIBUTES
+    {
+        return Err("unexpected memory attributes in mair_el1");
     }
 
-    init(table_start);
+    if get_user_table() != user_table {
+        return Err("ttbr0 does not hold the user table");
+    }
 
-    for i in (0..0x60_000).step_by(0x1000) {
-        if core::ptr::read_volatile(i as *const usize) != i {
-            return Err(());
-        }
+    let mut marker: u64 = 0;
+    let marker_address = core::ptr::addr_of_mut!(marker) as usize;
+
+    let ram = translate(marker_address).ok_or("kernel stack is not mapped")?;
+
+    if ram.physical_address != marker_address as u64 - KERNEL_ADDRESS_START {
+        return Err("kernel is not mapped at its physical address plus the kernel offset");
+    }
+
+    if ram.attributes != NORMAL_ATTRIBUTES || ram.shareability != INNER_SHAREABLE {
+        return Err("RAM is not mapped as cacheable inner shareable memory");
+    }
+
+    let device = translate(device_address).ok_or("peripherals are not mapped")?;
+
+    if device.attributes != DEVICE_ATTRIBUTES {
+        return Err("peripherals are not mapped as device memory");
     }
 
-    for i in (0x256_000..0x20000000).step_by(0x8000) {
-        if core::ptr::read_volatile(i as *const usize) != i {
-            return Err(());
+    // A write through the kernel mapping is seen through the identity map of the same memory
+    unsafe {
+        core::ptr::write_volatile(marker_address as *mut u64, 0x1234_5678_9ABC_DEF0);
+
+        if core::ptr::read_volatile(ram.physical_address as *const u64) != 0x1234_5678_9ABC_DEF0 {
+            return Err("kernel and identity mappings of RAM differ");
         }
     }
 
@@ -170,9 +215,17 @@ bitfield! {
         id: 0-1,
         attribute_index: 2-4,
         access_permission: 6-7,
+        shareability: 8-9,
         access_flag: 10-10,
-        address: 12-47
+        address: 12-47,
+        privileged_execute_never: 53-53,
+        unprivileged_execute_never: 54-54
     } with {
+        /// EL1 and EL0 may read and write
+        pub const READ_WRITE: u64 = 0b01;
+        /// EL1 and EL0 may only read
+        pub const READ_ONLY: u64 = 0b11;
+
         pub fn from(value: u64) -> Self {
             Self {
                 value
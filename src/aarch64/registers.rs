@@ -69,6 +69,7 @@ registers! {
         }
     },
     SystemControlRegister("sctlr_el1") {
+        instruction_cache_enable: 12-12,
         cache_enable: 2-2,
         translation_state: 0-0
     } with {
@@ -85,8 +86,238 @@ registers! {
     },
     ExceptionSyndromeRegister("esr_el1") {
         exception_class: 26-31,
+        instruction_length: 25-25,
+        instruction_specific_syndrome: 0-24,
         instruction_number: 0-15
     },
     ExceptionLinkRegister("elr_el1") {},
     FaultAddressRegister("far_el1") {}
 }
+
+/// The exception class of ESR_EL1 decoded together with the fields of its instruction specific
+/// syndrome that matter for crash reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionClass {
+    /// Unknown reason, which is what undefined instructions report
+    UndefinedInstruction,
+    TrappedWaitInstruction,
+    /// SIMD or floating point access while it is disabled in cpacr_el1
+    FloatingPointAccess,
+    IllegalExecutionState,
+    SupervisorCall {
+        immediate: u16,
+    },
+    InstructionAbort {
+        from_lower_level: bool,
+        fault: FaultStatus,
+        address: Option<usize>,
+    },
+    ProgramCounterAlignment {
+        address: usize,
+    },
+    DataAbort {
+        from_lower_level: bool,
+        fault: FaultStatus,
+        access: AccessType,
+        address: Option<usize>,
+    },
+    StackPointerAlignment,
+    FloatingPointException,
+    SystemError,
+    Breakpoint,
+    SoftwareStep,
+    Watchpoint {
+        address: usize,
+    },
+    BreakpointInstruction {
+        comment: u16,
+    },
+    Other {
+        class: u8,
+        syndrome: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessType {
+    Read,
+    Write,
+}
+
+/// The fault status code of instruction and data aborts. Faults during a table walk carry the
+/// level of the table that caused them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultStatus {
+    AddressSize { level: u8 },
+    Translation { level: u8 },
+    AccessFlag { level: u8 },
+    Permission { level: u8 },
+    SynchronousExternal,
+    SynchronousExternalOnWalk { level: u8 },
+    Alignment,
+    TLBConflict,
+    Other(u8),
+}
+
+impl FaultStatus {
+    pub fn from_code(code: u8) -> Self {
+        let level = code & 0b11;
+
+        match code & 0b11_1111 {
+            0b00_0000..=0b00_0011 => Self::AddressSize { level },
+            0b00_0100..=0b00_0111 => Self::Translation { level },
+            0b00_1000..=0b00_1011 => Self::AccessFlag { level },
+            0b00_1100..=0b00_1111 => Self::Permission { level },
+            0b01_0000 => Self::SynchronousExternal,
+            0b01_0100..=0b01_0111 => Self::SynchronousExternalOnWalk { level },
+            0b10_0001 => Self::Alignment,
+            0b11_0000 => Self::TLBConflict,
+            code => Self::Other(code),
+        }
+    }
+}
+
+impl ExceptionClass {
+    const WRITE_NOT_READ: usize = 1 << 6;
+    const FAULT_ADDRESS_NOT_VALID: usize = 1 << 10;
+
+    /// Decodes the syndrome. The fault address is only kept for the classes that set far_el1.
+    pub fn decode(
+        syndrome: ExceptionSyndromeRegister::RegisterBuffer,
+        fault_address: usize,
+    ) -> Self {
+        let class = syndrome.get_exception_class() as u8;
+        let iss = syndrome.get_instruction_specific_syndrome();
+
+        let abort_address = if iss & Self::FAULT_ADDRESS_NOT_VALID == 0 {
+            Some(fault_address)
+        } else {
+            None
+        };
+
+        match class {
+            0x00 => Self::UndefinedInstruction,
+            0x01 => Self::TrappedWaitInstruction,
+            0x07 => Self::FloatingPointAccess,
+            0x0E => Self::IllegalExecutionState,
+            0x15 => Self::SupervisorCall {
+                immediate: syndrome.get_instruction_number() as u16,
+            },
+            0x20 | 0x21 => Self::InstructionAbort {
+                from_lower_level: class == 0x20,
+                fault: FaultStatus::from_code(iss as u8),
+                address: abort_address,
+            },
+            0x22 => Self::ProgramCounterAlignment {
+                address: fault_address,
+            },
+            0x24 | 0x25 => Self::DataAbort {
+                from_lower_level: class == 0x24,
+                fault: FaultStatus::from_code(iss as u8),
+                access: if iss & Self::WRITE_NOT_READ != 0 {
+                    AccessType::Write
+                } else {
+                    AccessType::Read
+                },
+                address: abort_address,
+            },
+            0x26 => Self::StackPointerAlignment,
+            0x2C => Self::FloatingPointException,
+            0x2F => Self::SystemError,
+            0x30 | 0x31 => Self::Breakpoint,
+            0x32 | 0x33 => Self::SoftwareStep,
+            0x34 | 0x35 => Self::Watchpoint {
+                address: fault_address,
+            },
+            0x3C => Self::BreakpointInstruction {
+                comment: syndrome.get_instruction_number() as u16,
+            },
+            _ => Self::Other {
+                class,
+                syndrome: iss as u32,
+            },
+        }
+    }
+
+    /// Reads and decodes esr_el1 and far_el1
+    pub fn read() -> Self {
+        Self::decode(
+            ExceptionSyndromeRegister::read_to_buffer(),
+            FaultAddressRegister::read_to_buffer().value(),
+        )
+    }
+}
+
+impl core::fmt::Display for FaultStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::AddressSize { level } => core::write!(f, "address size fault level {}", level),
+            Self::Translation { level } => core::write!(f, "translation fault level {}", level),
+            Self::AccessFlag { level } => core::write!(f, "access flag fault level {}", level),
+            Self::Permission { level } => core::write!(f, "permission fault level {}", level),
+            Self::SynchronousExternal => core::write!(f, "synchronous external abort"),
+            Self::SynchronousExternalOnWalk { level } => {
+                core::write!(
+                    f,
+                    "synchronous external abort on table walk level {}",
+                    level
+                )
+            }
+            Self::Alignment => core::write!(f, "alignment fault"),
+            Self::TLBConflict => core::write!(f, "TLB conflict"),
+            Self::Other(code) => core::write!(f, "fault status {:#x}", code),
+        }
+    }
+}
+
+impl core::fmt::Display for ExceptionClass {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::UndefinedInstruction => core::write!(f, "undefined instruction"),
+            Self::TrappedWaitInstruction => core::write!(f, "trapped wfi or wfe"),
+            Self::FloatingPointAccess => core::write!(f, "trapped floating point access"),
+            Self::IllegalExecutionState => core::write!(f, "illegal execution state"),
+            Self::SupervisorCall { immediate } => core::write!(f, "svc #{}", immediate),
+            Self::InstructionAbort { fault, address, .. } => match address {
+                Some(address) => {
+                    core::write!(f, "{} on instruction fetch from {:#x}", fault, address)
+                }
+                None => core::write!(f, "{} on instruction fetch", fault),
+            },
+            Self::ProgramCounterAlignment { address } => {
+                core::write!(f, "misaligned program counter {:#x}", address)
+            }
+            Self::DataAbort {
+                fault,
+                access,
+                address,
+                ..
+            } => {
+                let access = match access {
+                    AccessType::Read => "read from",
+                    AccessType::Write => "write to",
+                };
+
+                match address {
+                    Some(address) => core::write!(f, "{} on {} {:#x}", fault, access, address),
+                    None => core::write!(f, "{} on {} an unknown address", fault, access),
+                }
+            }
+            Self::StackPointerAlignment => core::write!(f, "misaligned stack pointer"),
+            Self::FloatingPointException => core::write!(f, "floating point exception"),
+            Self::SystemError => core::write!(f, "system error"),
+            Self::Breakpoint => core::write!(f, "breakpoint"),
+            Self::SoftwareStep => core::write!(f, "software step"),
+            Self::Watchpoint { address } => core::write!(f, "watchpoint on {:#x}", address),
+            Self::BreakpointInstruction { comment } => core::write!(f, "brk #{}", comment),
+            Self::Other { class, syndrome } => {
+                core::write!(
+                    f,
+                    "exception class {:#x} with syndrome {:#x}",
+                    class,
+                    syndrome
+                )
+            }
+        }
+    }
+}
@@ -11,9 +11,25 @@ pub enum Syscall {
     Write = 0x9,
 
     Exec = 0xa,
+
+    GetPriority = 0xb,
+    SetPriority = 0xc,
+
+    ListThreads = 0xd,
+    Kill = 0xe,
+
+    FutexWait = 0xf,
+    FutexWake = 0x10,
+
+    Pipe = 0x11,
+    Dup = 0x12,
+
+    Brk = 0x13,
+    Mmap = 0x14,
+    Munmap = 0x15,
 }
 
-pub type SyscallArgs = [usize; 3];
+pub type SyscallArgs = [usize; 4];
 
 impl Syscall {
     pub fn from_u64(value: u64) -> Option<Self> {
@@ -29,6 +45,17 @@ impl Syscall {
             0x8 => Some(Syscall::Read),
             0x9 => Some(Syscall::Write),
             0xa => Some(Syscall::Exec),
+            0xb => Some(Syscall::GetPriority),
+            0xc => Some(Syscall::SetPriority),
+            0xd => Some(Syscall::ListThreads),
+            0xe => Some(Syscall::Kill),
+            0xf => Some(Syscall::FutexWait),
+            0x10 => Some(Syscall::FutexWake),
+            0x11 => Some(Syscall::Pipe),
+            0x12 => Some(Syscall::Dup),
+            0x13 => Some(Syscall::Brk),
+            0x14 => Some(Syscall::Mmap),
+            0x15 => Some(Syscall::Munmap),
             _ => None,
         }
     }
@@ -2,6 +2,7 @@ pub mod buddy_alloc;
 pub mod id_allocator;
 pub mod ll_alloc;
 pub mod page_allocator;
+pub mod slab_alloc;
 
 pub const fn align(addr: usize, align: usize) -> usize {
     (addr + align - 1) & !(align - 1)
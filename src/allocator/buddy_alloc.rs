@@ -1,8 +1,363 @@
+//! Buddy allocator for physical pages
+//!
+//! Pages are handed out in blocks of 2^order pages, which are aligned to their own size. A larger
+//! free block is split in halves, called buddies, until it has the requested order. A freed block
+//! is merged with its buddy for as long as the buddy is free as well, so that large contiguous
+//! runs become available again.
+
+use core::ptr;
+use core::slice;
+
+use super::align;
+use super::page_allocator::{Page, PageRef, PAGE_SIZE};
+use crate::aarch64::interrupt::IRQLock;
+
+/// Largest order of a block, 2^10 pages are 4 MiB
+pub const MAX_ORDER: usize = 10;
+
+/// The allocator of the page section, set up during boot. It is shared by the kernel and the
+/// kernel heap, so it must not allocate from the heap while it is locked.
+pub static PAGE_ALLOCATOR: IRQLock<Option<BuddyAllocator<'static>>> = IRQLock::new(None);
+
+/// Allocates 2^order contiguous pages from the page section, if it has been set up
+pub fn allocate_pages(order: usize) -> Option<PageRef> {
+    PAGE_ALLOCATOR.lock().as_mut()?.allocate(order)
+}
+
+/// Frees the block of pages starting at the address. Addresses outside of the page section are
+/// ignored, returns whether the address was in it.
+pub fn free_pages(address: usize) -> bool {
+    let mut page_allocator = PAGE_ALLOCATOR.lock();
+
+    match page_allocator.as_mut() {
+        Some(page_allocator) => match page_allocator.page_ref(address) {
+            Some(page_ref) => {
+                page_allocator.free(&page_ref);
+                true
+            }
+            None => false,
+        },
+        None => false,
+    }
+}
+
+/// Set in the state of the first page of a free block, the lower bits hold the order
+const FREE_BLOCK: u8 = 0x80;
+/// State of pages that do not start a block
+const NO_BLOCK: u8 = 0xFF;
+
+/// Links of a free block, stored in its first page
+struct FreeBlock {
+    next: *mut FreeBlock,
+    previous: *mut FreeBlock,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BuddyStats {
+    pub total_pages: usize,
+    pub free_pages: usize,
+    /// Number of free blocks of each order
+    pub free_blocks: [usize; MAX_ORDER + 1],
+    pub allocations: usize,
+    pub frees: usize,
+    /// Allocations for which no block was large enough
+    pub failed_allocations: usize,
+}
+
 pub struct BuddyAllocator<'a> {
-    heap: &'a [u8],
-    free_list: FreeList
+    /// State of the block starting at each page: the order of an allocated block, FREE_BLOCK with
+    /// the order of a free block or NO_BLOCK
+    blocks: &'a mut [u8],
+    pages: &'a mut [Page],
+    /// Free blocks of each order
+    free_lists: [*mut FreeBlock; MAX_ORDER + 1],
+    stats: BuddyStats,
 }
 
-struct FreeList {
+// The free lists only point into the pages the allocator owns
+unsafe impl Send for BuddyAllocator<'_> {}
+
+impl<'a> BuddyAllocator<'a> {
+    /// Manages the memory from start, the block states are stored at the start and the remaining
+    /// page aligned memory is free
+    pub fn with_start_and_length(start: usize, bytes: usize) -> Self {
+        let number_of_pages = bytes / (PAGE_SIZE + 1);
+        let page_start = align(start + number_of_pages, PAGE_SIZE);
+        let number_of_pages = number_of_pages.min((start + bytes - page_start) / PAGE_SIZE);
+
+        let (blocks, pages) = unsafe {
+            (
+                slice::from_raw_parts_mut(start as *mut u8, number_of_pages),
+                slice::from_raw_parts_mut(page_start as *mut Page, number_of_pages),
+            )
+        };
+
+        blocks.fill(NO_BLOCK);
+
+        let mut allocator = Self {
+            blocks,
+            pages,
+            free_lists: [ptr::null_mut(); MAX_ORDER + 1],
+            stats: BuddyStats {
+                total_pages: number_of_pages,
+                free_pages: number_of_pages,
+                ..BuddyStats::default()
+            },
+        };
+
+        // Cover the pages with the largest blocks that are aligned to their size
+        let mut index = 0;
+
+        while index < number_of_pages {
+            let order = (0..=MAX_ORDER)
+                .rev()
+                .find(|&order| {
+                    allocator.frame_number(index).is_multiple_of(1 << order)
+                        && index + (1 << order) <= number_of_pages
+                })
+                .unwrap_or(0);
+
+            allocator.push_free(index, order);
+
+            index += 1 << order;
+        }
+
+        allocator
+    }
+
+    /// Smallest order of a block that holds the number of pages
+    pub fn order_for_pages(pages: usize) -> usize {
+        pages.max(1).next_power_of_two().trailing_zeros() as usize
+    }
+
+    /// Allocates 2^order contiguous pages, aligned to their size
+    pub fn allocate(&mut self, order: usize) -> Option<PageRef> {
+        let found = (order..=MAX_ORDER).find(|&order| !self.free_lists[order].is_null());
+
+        let mut block_order = match found {
+            Some(block_order) => block_order,
+            None => {
+                self.stats.failed_allocations += 1;
+                return None;
+            }
+        };
+
+        let index = self.pop_free(block_order);
+
+        // Return the upper halves until the block has the requested size
+        while block_order > order {
+            block_order -= 1;
+            self.push_free(index + (1 << block_order), block_order);
+        }
+
+        self.blocks[index] = order as u8;
+        self.stats.free_pages -= 1 << order;
+        self.stats.allocations += 1;
+
+        Some(PageRef {
+            page: &mut self.pages[index] as *mut Page,
+            page_number: index,
+        })
+    }
+
+    pub fn allocate_page(&mut self) -> Option<PageRef> {
+        self.allocate(0)
+    }
+
+    /// Frees the block starting at the page, pages that do not start an allocated block are
+    /// ignored
+    pub fn free(&mut self, page: &PageRef) {
+        let mut index = page.page_number;
+
+        if index >= self.blocks.len() || self.blocks[index] & FREE_BLOCK != 0 {
+            return;
+        }
+
+        let mut order = self.blocks[index] as usize;
+
+        self.blocks[index] = NO_BLOCK;
+        self.stats.free_pages += 1 << order;
+        self.stats.frees += 1;
+
+        while order < MAX_ORDER {
+            let buddy = match self.buddy(index, order) {
+                Some(buddy) if self.blocks[buddy] == FREE_BLOCK | order as u8 => buddy,
+                _ => break,
+            };
+
+            self.remove_free(buddy, order);
+
+            index = index.min(buddy);
+            order += 1;
+        }
+
+        self.push_free(index, order);
+    }
 
-}
\ No newline at end of file
+    /// Returns a reference to the page starting at the given address if it is managed by this allocator
+    pub fn page_ref(&mut self, address: usize) -> Option<PageRef> {
+        let start = self.pages.as_ptr() as usize;
+        let end = start + self.pages.len() * PAGE_SIZE;
+
+        if address < start || address >= end || !address.is_multiple_of(PAGE_SIZE) {
+            return None;
+        }
+
+        let page_number = (address - start) / PAGE_SIZE;
+
+        Some(PageRef {
+            page: &mut self.pages[page_number] as *mut Page,
+            page_number,
+        })
+    }
+
+    pub fn stats(&self) -> BuddyStats {
+        self.stats
+    }
+
+    /// Page frame number of the page, blocks are aligned by frame number rather than by index so
+    /// that they are aligned in memory
+    fn frame_number(&self, index: usize) -> usize {
+        self.pages.as_ptr() as usize / PAGE_SIZE + index
+    }
+
+    /// Index of the block the block merges with, if it lies within the managed pages
+    fn buddy(&self, index: usize, order: usize) -> Option<usize> {
+        let frame = self.frame_number(index);
+        let buddy = (frame ^ (1 << order)).checked_sub(self.frame_number(0))?;
+
+        if buddy + (1 << order) <= self.pages.len() {
+            Some(buddy)
+        } else {
+            None
+        }
+    }
+
+    fn free_block(&mut self, index: usize) -> *mut FreeBlock {
+        &mut self.pages[index] as *mut Page as *mut FreeBlock
+    }
+
+    fn push_free(&mut self, index: usize, order: usize) {
+        let block = self.free_block(index);
+        let head = self.free_lists[order];
+
+        unsafe {
+            block.write(FreeBlock {
+                next: head,
+                previous: ptr::null_mut(),
+            });
+
+            if !head.is_null() {
+                (*head).previous = block;
+            }
+        }
+
+        self.free_lists[order] = block;
+        self.blocks[index] = FREE_BLOCK | order as u8;
+        self.stats.free_blocks[order] += 1;
+    }
+
+    fn pop_free(&mut self, order: usize) -> usize {
+        let index = (self.free_lists[order] as usize - self.pages.as_ptr() as usize) / PAGE_SIZE;
+
+        self.remove_free(index, order);
+
+        index
+    }
+
+    fn remove_free(&mut self, index: usize, order: usize) {
+        let block = self.free_block(index);
+
+        unsafe {
+            let FreeBlock { next, previous } = block.read();
+
+            if previous.is_null() {
+                self.free_lists[order] = next;
+            } else {
+                (*previous).next = next;
+            }
+
+            if !next.is_null() {
+                (*next).previous = previous;
+            }
+        }
+
+        self.blocks[index] = NO_BLOCK;
+        self.stats.free_blocks[order] -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGES: usize = 64;
+
+    #[repr(C, align(4096))]
+    struct Memory {
+        bytes: [u8; (PAGES + 1) * PAGE_SIZE],
+    }
+
+    fn with_allocator(test: impl FnOnce(&mut BuddyAllocator)) {
+        let mut memory = std::boxed::Box::new(Memory {
+            bytes: [0; (PAGES + 1) * PAGE_SIZE],
+        });
+
+        let mut allocator = BuddyAllocator::with_start_and_length(
+            memory.bytes.as_mut_ptr() as usize,
+            memory.bytes.len(),
+        );
+
+        test(&mut allocator);
+    }
+
+    #[test]
+    fn test_order_for_pages() {
+        assert_eq!(BuddyAllocator::order_for_pages(0), 0);
+        assert_eq!(BuddyAllocator::order_for_pages(1), 0);
+        assert_eq!(BuddyAllocator::order_for_pages(3), 2);
+        assert_eq!(BuddyAllocator::order_for_pages(4), 2);
+    }
+
+    #[test]
+    fn test_allocate_all_pages() {
+        with_allocator(|allocator| {
+            let total = allocator.stats().total_pages;
+
+            for _ in 0..total {
+                assert!(allocator.allocate_page().is_some());
+            }
+
+            assert!(allocator.allocate_page().is_none());
+            assert_eq!(allocator.stats().free_pages, 0);
+        });
+    }
+
+    #[test]
+    fn test_blocks_are_aligned() {
+        with_allocator(|allocator| {
+            let block = allocator.allocate(3).unwrap();
+
+            assert_eq!(block.page as usize % (8 * PAGE_SIZE), 0);
+        });
+    }
+
+    #[test]
+    fn test_free_coalesces() {
+        with_allocator(|allocator| {
+            let before = allocator.stats();
+
+            let pages: std::vec::Vec<PageRef> =
+                (0..8).map(|_| allocator.allocate_page().unwrap()).collect();
+
+            for page in &pages {
+                allocator.free(page);
+            }
+
+            let after = allocator.stats();
+
+            assert_eq!(after.free_pages, before.free_pages);
+            assert_eq!(after.free_blocks, before.free_blocks);
+        });
+    }
+}
@@ -24,6 +24,10 @@ pub struct AllocatorStats {
     pub blocks: usize,
     pub allocs: usize,
     pub frees: usize,
+    /// Pages split into objects of the slab allocator
+    pub slab_pages: usize,
+    /// Pages taken for large allocations once the heap had no room left
+    pub large_pages: usize,
 }
 
 impl AllocatorStats {
@@ -33,6 +37,8 @@ impl AllocatorStats {
             blocks: 0,
             allocs: 0,
             frees: 0,
+            slab_pages: 0,
+            large_pages: 0,
         }
     }
 }
@@ -44,6 +50,8 @@ impl Default for AllocatorStats {
             blocks: 0,
             allocs: 0,
             frees: 0,
+            slab_pages: 0,
+            large_pages: 0,
         }
     }
 }
@@ -51,11 +59,7 @@ impl Default for AllocatorStats {
 unsafe impl GlobalAlloc for SpinMutex<LinkedListAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let interrupt_state = pop_irq_state();
-        let allocator = &mut self.lock();
-
-        let allocation = allocator
-            .allocate(layout)
-            .map_or(core::ptr::null_mut(), |b| b as *const FreeBlock as *mut u8);
+        let allocation = self.lock().allocate_block(layout);
 
         set_irq_state(interrupt_state);
 
@@ -118,13 +122,27 @@ impl LinkedListAllocator {
         }
     }
 
+    pub fn stats(&self) -> AllocatorStats {
+        AllocatorStats {
+            free_space: self.free_space,
+            ..self.stats
+        }
+    }
+
+    /// Allocates memory for the layout, null if no free block fits it
+    pub fn allocate_block(&mut self, layout: Layout) -> *mut u8 {
+        self.allocate(layout)
+            .map_or(core::ptr::null_mut(), |b| b as *const FreeBlock as *mut u8)
+    }
+
     // Return the smallest block larger than the size and of the correct alignment
     fn allocate(&mut self, layout: Layout) -> Option<&mut FreeBlock> {
-        self.stats.allocs += 1;
         let (size, align) = Self::expand_to_min(layout);
-        self.free_space -= size;
         // TODO: is it safe to discard next?
         if let Ok((free, _)) = self.free_list.fit_in_block(size, align) {
+            // Failed allocations are not counted, as the slab allocator retries them elsewhere
+            self.stats.allocs += 1;
+            self.free_space -= size;
             Some(free)
         } else {
             None
@@ -132,14 +150,14 @@ impl LinkedListAllocator {
     }
 
     //TODO coalesce neighboring blocks
-    fn free(&mut self, start: usize, size: usize) {
+    pub fn free(&mut self, start: usize, size: usize) {
         self.free_space += size;
         self.stats.frees += 1;
 
         // TODO: should be a single source of truth for expanding blocks
         let size = size.max(mem::size_of::<FreeBlock>());
 
-        if start % mem::align_of::<FreeBlock>() != 0 {
+        if !start.is_multiple_of(mem::align_of::<FreeBlock>()) {
             panic!("Incompatible memory alignment of freed block. Block address: {:x}, needs alignment {}", start, mem::align_of::<FreeBlock>());
         }
 
@@ -0,0 +1,439 @@
+//! Kernel heap that serves small allocations from slabs
+//!
+//! Each size class has slabs: blocks of memory that start with a header and are cut into equally
+//! sized objects. Allocating and freeing an object takes constant time and never fragments the
+//! heap. Objects are aligned to their size. Allocations larger than the largest class go to the
+//! linked list allocator.
+//!
+//! The heap grows with blocks of the page allocator once it has been set up. Slabs are taken from
+//! it, as are large allocations the linked list allocator has no room for. Slabs that become empty
+//! and blocks of large allocations are handed back when they are freed, so the heap shrinks again.
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::fmt::{Debug, Error, Formatter};
+use core::mem;
+use core::ptr;
+
+use super::align;
+use super::buddy_alloc::{self, BuddyAllocator};
+use super::ll_alloc::{AllocatorStats, LinkedListAllocator};
+use super::page_allocator::PAGE_SIZE;
+use crate::aarch64::interrupt::{pop_irq_state, set_irq_state};
+use crate::sync::SpinMutex;
+
+/// Object sizes of the size classes
+pub const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Slabs hold at least this many objects, so that the header wastes little of larger classes
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+/// Link of a free object, stored in the object
+#[derive(Debug)]
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// Header at the start of a slab, slabs are aligned to their size so that the header of an object
+/// is found by masking its address
+#[derive(Debug)]
+struct Slab {
+    /// Neighbours in the list of slabs of the class that have free objects
+    next: *mut Slab,
+    previous: *mut Slab,
+    free: *mut FreeObject,
+    in_use: usize,
+    /// Whether the slab is a block of the page allocator, rather than memory of the heap
+    from_pages: bool,
+}
+
+#[derive(Debug)]
+pub struct SlabAllocator {
+    /// Slabs with free objects of each size class, full slabs are only found through their objects
+    partial_slabs: [*mut Slab; SIZE_CLASSES.len()],
+    /// Allocations that do not fit a size class
+    fallback: LinkedListAllocator,
+    /// Bytes in free objects
+    free_space: usize,
+    allocs: usize,
+    frees: usize,
+    slab_pages: usize,
+    large_pages: usize,
+}
+
+unsafe impl GlobalAlloc for SpinMutex<SlabAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let interrupt_state = pop_irq_state();
+        let allocation = self.lock().allocate(layout);
+
+        set_irq_state(interrupt_state);
+
+        allocation
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let interrupt_state = pop_irq_state();
+        self.lock().free(ptr, layout);
+        set_irq_state(interrupt_state);
+    }
+}
+
+impl SpinMutex<SlabAllocator> {
+    pub fn stats(&self) -> AllocatorStats {
+        self.lock().stats()
+    }
+}
+
+impl Debug for SpinMutex<SlabAllocator> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        self.lock().fmt(f)
+    }
+}
+
+impl SlabAllocator {
+    pub const fn new() -> Self {
+        Self {
+            partial_slabs: [ptr::null_mut(); SIZE_CLASSES.len()],
+            fallback: LinkedListAllocator::new(),
+            free_space: 0,
+            allocs: 0,
+            frees: 0,
+            slab_pages: 0,
+            large_pages: 0,
+        }
+    }
+
+    /// Hands the boot heap to the linked list allocator
+    pub fn init(&mut self, start: usize, size: usize) {
+        self.fallback.init(start, size);
+    }
+
+    /// Counters of both allocators, the free space includes free slab objects
+    pub fn stats(&self) -> AllocatorStats {
+        let fallback = self.fallback.stats();
+
+        AllocatorStats {
+            free_space: fallback.free_space + self.free_space,
+            blocks: fallback.blocks,
+            allocs: fallback.allocs + self.allocs,
+            frees: fallback.frees + self.frees,
+            slab_pages: self.slab_pages,
+            large_pages: self.large_pages,
+        }
+    }
+
+    /// Index of the smallest size class that holds the layout
+    fn size_class(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+
+        SIZE_CLASSES
+            .iter()
+            .position(|&class_size| class_size >= size)
+    }
+
+    fn slab_size(class: usize) -> usize {
+        (SIZE_CLASSES[class] * MIN_OBJECTS_PER_SLAB).max(PAGE_SIZE)
+    }
+
+    /// Offset of the first object, behind the header
+    fn first_object(class: usize) -> usize {
+        align(mem::size_of::<Slab>(), SIZE_CLASSES[class])
+    }
+
+    /// Order of the page block that holds the layout at its alignment
+    fn large_order(layout: Layout) -> usize {
+        BuddyAllocator::order_for_pages(layout.size().max(layout.align()).div_ceil(PAGE_SIZE))
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let class = match Self::size_class(layout) {
+            Some(class) => class,
+            None => return self.allocate_large(layout),
+        };
+
+        if self.partial_slabs[class].is_null() && !self.add_slab(class) {
+            return ptr::null_mut();
+        }
+
+        let slab = self.partial_slabs[class];
+
+        unsafe {
+            let object = (*slab).free;
+
+            (*slab).free = (*object).next;
+            (*slab).in_use += 1;
+
+            if (*slab).free.is_null() {
+                self.unlink(slab, class);
+            }
+
+            self.free_space -= SIZE_CLASSES[class];
+            self.allocs += 1;
+
+            object as *mut u8
+        }
+    }
+
+    fn free(&mut self, ptr: *mut u8, layout: Layout) {
+        let class = match Self::size_class(layout) {
+            Some(class) => class,
+            None => return self.free_large(ptr, layout),
+        };
+
+        let slab = (ptr as usize & !(Self::slab_size(class) - 1)) as *mut Slab;
+        let object = ptr as *mut FreeObject;
+
+        unsafe {
+            let was_full = (*slab).free.is_null();
+
+            object.write(FreeObject { next: (*slab).free });
+
+            (*slab).free = object;
+            (*slab).in_use -= 1;
+
+            if was_full {
+                self.link(slab, class);
+            }
+
+            self.free_space += SIZE_CLASSES[class];
+            self.frees += 1;
+
+            // The last slab with free objects is kept, so that a class does not take and return a
+            // slab on every allocation
+            if (*slab).in_use == 0 && !((*slab).next.is_null() && (*slab).previous.is_null()) {
+                self.release_slab(slab, class);
+            }
+        }
+    }
+
+    /// Allocates from the linked list allocator, or a block of pages of its own once the heap has
+    /// no room left
+    fn allocate_large(&mut self, layout: Layout) -> *mut u8 {
+        let allocation = self.fallback.allocate_block(layout);
+
+        if !allocation.is_null() {
+            return allocation;
+        }
+
+        let order = Self::large_order(layout);
+
+        match buddy_alloc::allocate_pages(order) {
+            Some(block) => {
+                self.large_pages += 1 << order;
+                self.allocs += 1;
+
+                block.page as *mut u8
+            }
+            None => ptr::null_mut(),
+        }
+    }
+
+    fn free_large(&mut self, ptr: *mut u8, layout: Layout) {
+        // Heap memory lies outside of the page section
+        if buddy_alloc::free_pages(ptr as usize) {
+            self.large_pages -= 1 << Self::large_order(layout);
+            self.frees += 1;
+        } else {
+            self.fallback.free(ptr as usize, layout.size());
+        }
+    }
+
+    /// Sets up a new slab for the size class, returns false if there is no memory left
+    fn add_slab(&mut self, class: usize) -> bool {
+        let size = Self::slab_size(class);
+        let order = BuddyAllocator::order_for_pages(size / PAGE_SIZE);
+
+        let (start, from_pages) = match buddy_alloc::allocate_pages(order) {
+            Some(block) => (block.page as *mut u8, true),
+            None => (
+                self.fallback
+                    .allocate_block(Layout::from_size_align(size, size).unwrap()),
+                false,
+            ),
+        };
+
+        if start.is_null() {
+            return false;
+        }
+
+        let slab = start as *mut Slab;
+        let object_size = SIZE_CLASSES[class];
+        let mut free = ptr::null_mut();
+
+        // Queued back to front, so that objects are handed out in address order
+        for offset in (Self::first_object(class)..size).step_by(object_size).rev() {
+            let object = unsafe { start.add(offset) } as *mut FreeObject;
+
+            unsafe {
+                object.write(FreeObject { next: free });
+            }
+
+            free = object;
+        }
+
+        unsafe {
+            slab.write(Slab {
+                next: ptr::null_mut(),
+                previous: ptr::null_mut(),
+                free,
+                in_use: 0,
+                from_pages,
+            });
+
+            self.link(slab, class);
+        }
+        self.free_space += size - Self::first_object(class);
+        self.slab_pages += size / PAGE_SIZE;
+
+        true
+    }
+
+    /// Returns an empty slab to where it was taken from
+    unsafe fn release_slab(&mut self, slab: *mut Slab, class: usize) {
+        let size = Self::slab_size(class);
+
+        self.unlink(slab, class);
+        self.free_space -= size - Self::first_object(class);
+        self.slab_pages -= size / PAGE_SIZE;
+
+        if (*slab).from_pages {
+            buddy_alloc::free_pages(slab as usize);
+        } else {
+            self.fallback.free(slab as usize, size);
+        }
+    }
+
+    /// Adds the slab to the front of the partial slabs of the class
+    unsafe fn link(&mut self, slab: *mut Slab, class: usize) {
+        let head = self.partial_slabs[class];
+
+        (*slab).next = head;
+        (*slab).previous = ptr::null_mut();
+
+        if !head.is_null() {
+            (*head).previous = slab;
+        }
+
+        self.partial_slabs[class] = slab;
+    }
+
+    unsafe fn unlink(&mut self, slab: *mut Slab, class: usize) {
+        let Slab { next, previous, .. } = *slab;
+
+        if previous.is_null() {
+            self.partial_slabs[class] = next;
+        } else {
+            (*previous).next = next;
+        }
+
+        if !next.is_null() {
+            (*next).previous = previous;
+        }
+
+        (*slab).next = ptr::null_mut();
+        (*slab).previous = ptr::null_mut();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEAP_SIZE: usize = 16 * PAGE_SIZE;
+
+    #[repr(C, align(4096))]
+    struct Heap {
+        memory: [u8; HEAP_SIZE],
+    }
+
+    fn with_allocator(test: impl FnOnce(&SpinMutex<SlabAllocator>)) {
+        let mut heap = std::boxed::Box::new(Heap {
+            memory: [0; HEAP_SIZE],
+        });
+
+        let allocator = SpinMutex::new(SlabAllocator::new());
+
+        allocator
+            .lock()
+            .init(heap.memory.as_mut_ptr() as usize, HEAP_SIZE);
+
+        test(&allocator);
+    }
+
+    #[test]
+    fn test_size_class() {
+        let class =
+            |size, align| SlabAllocator::size_class(Layout::from_size_align(size, align).unwrap());
+
+        assert_eq!(class(1, 1), Some(0));
+        assert_eq!(class(17, 8), Some(1));
+        assert_eq!(class(8, 64), Some(2));
+        assert_eq!(class(2048, 8), Some(7));
+        assert_eq!(class(2049, 8), None);
+    }
+
+    #[test]
+    fn test_objects_are_aligned_and_reused() {
+        with_allocator(|allocator| unsafe {
+            let layout = Layout::from_size_align(48, 8).unwrap();
+
+            let first = allocator.alloc(layout);
+            let second = allocator.alloc(layout);
+
+            assert_ne!(first, ptr::null_mut());
+            assert_eq!(first as usize % 64, 0);
+            assert_eq!(second as usize, first as usize + 64);
+
+            allocator.dealloc(first, layout);
+
+            assert_eq!(allocator.alloc(layout), first);
+        });
+    }
+
+    #[test]
+    fn test_stats_cover_both_allocators() {
+        with_allocator(|allocator| unsafe {
+            let small = Layout::from_size_align(32, 8).unwrap();
+            let large = Layout::from_size_align(3000, 8).unwrap();
+
+            let small_allocation = allocator.alloc(small);
+            let large_allocation = allocator.alloc(large);
+
+            allocator.dealloc(small_allocation, small);
+            allocator.dealloc(large_allocation, large);
+
+            let stats = allocator.stats();
+
+            // Without a page allocator the slab is allocated from the heap as well
+            assert_eq!(stats.allocs, 3);
+            assert_eq!(stats.frees, 2);
+            assert_eq!(stats.slab_pages, 1);
+        });
+    }
+
+    #[test]
+    fn test_empty_slabs_are_released() {
+        with_allocator(|allocator| unsafe {
+            let layout = Layout::from_size_align(2048, 8).unwrap();
+            let objects_per_slab =
+                (SlabAllocator::slab_size(7) - SlabAllocator::first_object(7)) / 2048;
+
+            let allocations: std::vec::Vec<*mut u8> = (0..2 * objects_per_slab)
+                .map(|_| allocator.alloc(layout))
+                .collect();
+
+            assert_eq!(
+                allocator.stats().slab_pages,
+                2 * SlabAllocator::slab_size(7) / PAGE_SIZE
+            );
+
+            for &allocation in &allocations {
+                allocator.dealloc(allocation, layout);
+            }
+
+            assert_eq!(
+                allocator.stats().slab_pages,
+                SlabAllocator::slab_size(7) / PAGE_SIZE
+            );
+        });
+    }
+}
@@ -1,29 +1,30 @@
-use crate::elf;
+use alloc::vec::Vec;
 
 #[repr(C)]
 #[derive(Debug)]
 pub struct ELF64Header {
-    elf_identification: ELFIdentification,
-    object_file_type: ObjectFileType,
-    e_machine: u16,
+    pub elf_identification: ELFIdentification,
+    pub object_file_type: ObjectFileType,
+    pub e_machine: u16,
     e_version: u32,
     pub program_entry_address: u64, // Address to first transfer execution to
     pub program_header_offset: u64,
-    section_header_offset: u64,
+    pub section_header_offset: u64,
     e_flags: u32,
     pub elf_header_size: u16,
     pub program_header_entry_size: u16,
     pub program_header_number: u16,
-    section_header_entry_size: u16,
-    section_header_entry_num: u16,
-    string_table_entry_number: u16,
+    pub section_header_entry_size: u16,
+    pub section_header_entry_num: u16,
+    /// Index of the section that holds the section names
+    pub string_table_entry_number: u16,
 }
 
 #[repr(C)]
 #[derive(Debug)]
 pub struct ELFIdentification {
     magic_number: [u8; 4],
-    file_class: ELFFileClass,
+    pub file_class: ELFFileClass,
     data_encoding: u8,
     file_version: u8,
     abi_identification: u8,
@@ -32,8 +33,8 @@ pub struct ELFIdentification {
 }
 
 #[repr(u16)]
-#[derive(Debug)]
-enum ObjectFileType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectFileType {
     None = 0x0,
     RelocatableFile = 0x1,
     ExecutableFile = 0x2,
@@ -42,7 +43,7 @@ enum ObjectFileType {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ELFFileClass {
     Invalid = 0x0,
     Class32 = 0x1,
@@ -53,6 +54,10 @@ impl ELFIdentification {
     const MAGIC_NUMBER: [u8; 4] = [0x7f, b'E', b'L', b'F'];
 }
 
+impl ELF64Header {
+    pub const MACHINE_AARCH64: u16 = 183;
+}
+
 // TODO: Could we implement this all as transmutations with checks? See the file system
 impl TryFrom<&[u8]> for ELF64Header {
     type Error = &'static str;
@@ -140,7 +145,6 @@ impl TryFrom<u8> for ELFFileClass {
     }
 }
 
-#[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct ProgramHeader {
     pub program_type: ProgramType,
@@ -150,7 +154,7 @@ pub struct ProgramHeader {
     physical_address: u64,
     pub file_size: u64,
     pub memory_size: u64,
-    alignment: u64,
+    pub alignment: u64,
 }
 
 #[repr(u32)]
@@ -164,4 +168,708 @@ pub enum ProgramType {
     Shlib = 0x5,
     PHeader = 0x6,
     ThreadLocalStorage = 0x7,
+    /// Operating system or processor specific segments such as PT_GNU_STACK
+    Other = 0xFFFF_FFFF,
+}
+
+impl ProgramHeader {
+    pub const SIZE: usize = 56;
+
+    const FLAG_EXECUTE: u32 = 0x1;
+    const FLAG_WRITE: u32 = 0x2;
+    const FLAG_READ: u32 = 0x4;
+
+    pub fn is_executable(&self) -> bool {
+        self.flags & Self::FLAG_EXECUTE != 0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.flags & Self::FLAG_WRITE != 0
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.flags & Self::FLAG_READ != 0
+    }
+}
+
+impl TryFrom<&[u8]> for ProgramHeader {
+    type Error = &'static str;
+
+    fn try_from(buffer: &[u8]) -> Result<Self, Self::Error> {
+        if buffer.len() < Self::SIZE {
+            return Err("Buffer not large enough to contain program header");
+        }
+
+        Ok(Self {
+            program_type: u32::from_le_bytes(buffer[0..4].try_into().unwrap()).into(),
+            flags: u32::from_le_bytes(buffer[4..8].try_into().unwrap()),
+            offset: u64::from_le_bytes(buffer[8..16].try_into().unwrap()),
+            virtual_address: u64::from_le_bytes(buffer[16..24].try_into().unwrap()),
+            physical_address: u64::from_le_bytes(buffer[24..32].try_into().unwrap()),
+            file_size: u64::from_le_bytes(buffer[32..40].try_into().unwrap()),
+            memory_size: u64::from_le_bytes(buffer[40..48].try_into().unwrap()),
+            alignment: u64::from_le_bytes(buffer[48..56].try_into().unwrap()),
+        })
+    }
+}
+
+impl From<u32> for ProgramType {
+    fn from(value: u32) -> Self {
+        match value {
+            0x0 => ProgramType::Ignored,
+            0x1 => ProgramType::Loadable,
+            0x2 => ProgramType::Dynamic,
+            0x3 => ProgramType::Interpreter,
+            0x4 => ProgramType::Note,
+            0x5 => ProgramType::Shlib,
+            0x6 => ProgramType::PHeader,
+            0x7 => ProgramType::ThreadLocalStorage,
+            _ => ProgramType::Other,
+        }
+    }
+}
+
+/// An entry of the dynamic section
+#[derive(Debug, Clone, Copy)]
+pub struct DynamicEntry {
+    pub tag: u64,
+    pub value: u64,
+}
+
+impl DynamicEntry {
+    pub const SIZE: usize = 16;
+
+    pub const NULL: u64 = 0;
+    pub const PLT_RELOCATION_SIZE: u64 = 2;
+    pub const RELA: u64 = 7;
+    pub const RELA_SIZE: u64 = 8;
+    pub const RELA_ENTRY_SIZE: u64 = 9;
+    pub const REL: u64 = 17;
+    pub const REL_SIZE: u64 = 18;
+    pub const PLT_RELOCATION_TYPE: u64 = 20;
+    pub const PLT_RELOCATIONS: u64 = 23;
+    pub const RELR: u64 = 36;
+}
+
+impl TryFrom<&[u8]> for DynamicEntry {
+    type Error = &'static str;
+
+    fn try_from(buffer: &[u8]) -> Result<Self, Self::Error> {
+        if buffer.len() < Self::SIZE {
+            return Err("Buffer not large enough to contain dynamic entry");
+        }
+
+        Ok(Self {
+            tag: u64::from_le_bytes(buffer[0..8].try_into().unwrap()),
+            value: u64::from_le_bytes(buffer[8..16].try_into().unwrap()),
+        })
+    }
+}
+
+/// A relocation with an explicit addend (Elf64_Rela)
+#[derive(Debug, Clone, Copy)]
+pub struct RelocationEntry {
+    pub offset: u64,
+    info: u64,
+    pub addend: i64,
+}
+
+impl RelocationEntry {
+    pub const SIZE: usize = 24;
+
+    pub const R_AARCH64_NONE: u32 = 0;
+    pub const R_AARCH64_RELATIVE: u32 = 1027;
+
+    pub fn relocation_type(&self) -> u32 {
+        (self.info & 0xFFFF_FFFF) as u32
+    }
+
+    pub fn symbol_index(&self) -> u32 {
+        (self.info >> 32) as u32
+    }
+}
+
+impl TryFrom<&[u8]> for RelocationEntry {
+    type Error = &'static str;
+
+    fn try_from(buffer: &[u8]) -> Result<Self, Self::Error> {
+        if buffer.len() < Self::SIZE {
+            return Err("Buffer not large enough to contain relocation");
+        }
+
+        Ok(Self {
+            offset: u64::from_le_bytes(buffer[0..8].try_into().unwrap()),
+            info: u64::from_le_bytes(buffer[8..16].try_into().unwrap()),
+            addend: i64::from_le_bytes(buffer[16..24].try_into().unwrap()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SectionHeader {
+    name: u32,
+    pub section_type: SectionType,
+    pub flags: u64,
+    pub address: u64,
+    pub offset: u64,
+    pub size: u64,
+    /// Section index of the string table for symbol tables
+    pub link: u32,
+    pub info: u32,
+    pub alignment: u64,
+    pub entry_size: u64,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionType {
+    Null = 0x0,
+    ProgramBits = 0x1,
+    SymbolTable = 0x2,
+    StringTable = 0x3,
+    Rela = 0x4,
+    Hash = 0x5,
+    Dynamic = 0x6,
+    Note = 0x7,
+    NoBits = 0x8,
+    Rel = 0x9,
+    DynamicSymbolTable = 0xB,
+    /// Operating system or processor specific sections
+    Other = 0xFFFF_FFFF,
+}
+
+impl SectionHeader {
+    pub const SIZE: usize = 64;
+}
+
+impl TryFrom<&[u8]> for SectionHeader {
+    type Error = &'static str;
+
+    fn try_from(buffer: &[u8]) -> Result<Self, Self::Error> {
+        if buffer.len() < Self::SIZE {
+            return Err("Buffer not large enough to contain section header");
+        }
+
+        Ok(Self {
+            name: u32::from_le_bytes(buffer[0..4].try_into().unwrap()),
+            section_type: u32::from_le_bytes(buffer[4..8].try_into().unwrap()).into(),
+            flags: u64::from_le_bytes(buffer[8..16].try_into().unwrap()),
+            address: u64::from_le_bytes(buffer[16..24].try_into().unwrap()),
+            offset: u64::from_le_bytes(buffer[24..32].try_into().unwrap()),
+            size: u64::from_le_bytes(buffer[32..40].try_into().unwrap()),
+            link: u32::from_le_bytes(buffer[40..44].try_into().unwrap()),
+            info: u32::from_le_bytes(buffer[44..48].try_into().unwrap()),
+            alignment: u64::from_le_bytes(buffer[48..56].try_into().unwrap()),
+            entry_size: u64::from_le_bytes(buffer[56..64].try_into().unwrap()),
+        })
+    }
+}
+
+impl From<u32> for SectionType {
+    fn from(value: u32) -> Self {
+        match value {
+            0x0 => SectionType::Null,
+            0x1 => SectionType::ProgramBits,
+            0x2 => SectionType::SymbolTable,
+            0x3 => SectionType::StringTable,
+            0x4 => SectionType::Rela,
+            0x5 => SectionType::Hash,
+            0x6 => SectionType::Dynamic,
+            0x7 => SectionType::Note,
+            0x8 => SectionType::NoBits,
+            0x9 => SectionType::Rel,
+            0xB => SectionType::DynamicSymbolTable,
+            _ => SectionType::Other,
+        }
+    }
+}
+
+/// An entry of a symbol table (Elf64_Sym)
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    name: u32,
+    info: u8,
+    other: u8,
+    pub section_index: u16,
+    pub value: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolType {
+    NoType = 0x0,
+    Object = 0x1,
+    Function = 0x2,
+    Section = 0x3,
+    File = 0x4,
+    ThreadLocalStorage = 0x6,
+    Other = 0xF,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolBinding {
+    Local = 0x0,
+    Global = 0x1,
+    Weak = 0x2,
+    Other = 0xF,
+}
+
+impl Symbol {
+    pub const SIZE: usize = 24;
+
+    /// Section index of symbols that are not defined in this file
+    pub const UNDEFINED_SECTION: u16 = 0;
+
+    pub fn symbol_type(&self) -> SymbolType {
+        match self.info & 0xF {
+            0x0 => SymbolType::NoType,
+            0x1 => SymbolType::Object,
+            0x2 => SymbolType::Function,
+            0x3 => SymbolType::Section,
+            0x4 => SymbolType::File,
+            0x6 => SymbolType::ThreadLocalStorage,
+            _ => SymbolType::Other,
+        }
+    }
+
+    pub fn binding(&self) -> SymbolBinding {
+        match self.info >> 4 {
+            0x0 => SymbolBinding::Local,
+            0x1 => SymbolBinding::Global,
+            0x2 => SymbolBinding::Weak,
+            _ => SymbolBinding::Other,
+        }
+    }
+
+    pub fn is_defined(&self) -> bool {
+        self.section_index != Self::UNDEFINED_SECTION
+    }
+
+    /// Whether the symbol covers the address. Symbols without a size only match their own address.
+    pub fn contains(&self, address: u64) -> bool {
+        address == self.value || (self.value < address && address - self.value < self.size)
+    }
+}
+
+impl TryFrom<&[u8]> for Symbol {
+    type Error = &'static str;
+
+    fn try_from(buffer: &[u8]) -> Result<Self, Self::Error> {
+        if buffer.len() < Self::SIZE {
+            return Err("Buffer not large enough to contain symbol");
+        }
+
+        Ok(Self {
+            name: u32::from_le_bytes(buffer[0..4].try_into().unwrap()),
+            info: buffer[4],
+            other: buffer[5],
+            section_index: u16::from_le_bytes(buffer[6..8].try_into().unwrap()),
+            value: u64::from_le_bytes(buffer[8..16].try_into().unwrap()),
+            size: u64::from_le_bytes(buffer[16..24].try_into().unwrap()),
+        })
+    }
+}
+
+/// A section of null terminated strings, indexed by byte offset
+#[derive(Debug, Clone, Copy)]
+pub struct StringTable<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StringTable<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    /// Returns the string starting at the offset, or None if it is out of bounds or not UTF-8
+    pub fn get(&self, offset: u32) -> Option<&'a str> {
+        let start = self.data.get(offset as usize..)?;
+        let length = start.iter().position(|&byte| byte == 0)?;
+
+        core::str::from_utf8(&start[..length]).ok()
+    }
+}
+
+/// A symbol table together with the string table that holds its names
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolTable<'a> {
+    data: &'a [u8],
+    entry_size: usize,
+    strings: StringTable<'a>,
+}
+
+impl<'a> SymbolTable<'a> {
+    /// A symbol table from the raw contents of its section, for files that are not fully in memory
+    pub fn new(data: &'a [u8], entry_size: usize, strings: StringTable<'a>) -> Self {
+        Self {
+            data,
+            entry_size: entry_size.max(Symbol::SIZE),
+            strings,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len() / self.entry_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<Symbol> {
+        let start = index.checked_mul(self.entry_size)?;
+
+        Symbol::try_from(self.data.get(start..start + self.entry_size)?).ok()
+    }
+
+    pub fn name(&self, symbol: &Symbol) -> Option<&'a str> {
+        self.strings.get(symbol.name)
+    }
+
+    /// Iterates over the symbols and their names. The null symbol at index 0 is included.
+    pub fn iter(&self) -> impl Iterator<Item = (Symbol, Option<&'a str>)> + '_ {
+        (0..self.len()).filter_map(move |index| {
+            let symbol = self.get(index)?;
+
+            Some((symbol, self.name(&symbol)))
+        })
+    }
+
+    /// Finds the function or object symbol covering the address and returns its name and the
+    /// offset of the address into the symbol. If no symbol covers the address, the closest defined
+    /// symbol below it is used.
+    pub fn resolve(&self, address: u64) -> Option<(&'a str, u64)> {
+        let mut closest: Option<Symbol> = None;
+
+        for (symbol, name) in self.iter() {
+            // AArch64 mapping symbols such as $x and $d only mark the start of code or data
+            if !symbol.is_defined()
+                || name.is_none_or(|name| name.is_empty() || name.starts_with('$'))
+                || !matches!(
+                    symbol.symbol_type(),
+                    SymbolType::Function | SymbolType::Object | SymbolType::NoType
+                )
+                || symbol.value > address
+            {
+                continue;
+            }
+
+            if symbol.contains(address) && symbol.size != 0 {
+                closest = Some(symbol);
+                break;
+            }
+
+            if closest.is_none_or(|closest| symbol.value > closest.value) {
+                closest = Some(symbol);
+            }
+        }
+
+        let symbol = closest?;
+
+        Some((self.name(&symbol)?, address - symbol.value))
+    }
+}
+
+/// An ELF file held in memory, with its section headers parsed
+#[derive(Debug)]
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    pub header: ELF64Header,
+    section_headers: Vec<SectionHeader>,
+}
+
+impl<'a> ElfFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+        let header = ELF64Header::try_from(data)?;
+
+        let section_headers = if header.section_header_entry_num == 0 {
+            Vec::new()
+        } else {
+            let entry_size = header.section_header_entry_size as usize;
+
+            if entry_size < SectionHeader::SIZE {
+                return Err("Section header entries are too small");
+            }
+
+            let table = Self::slice(
+                data,
+                header.section_header_offset,
+                (entry_size * header.section_header_entry_num as usize) as u64,
+            )
+            .ok_or("Section header table lies outside of the file")?;
+
+            table
+                .chunks_exact(entry_size)
+                .map(SectionHeader::try_from)
+                .collect::<Result<Vec<SectionHeader>, _>>()?
+        };
+
+        Ok(Self {
+            data,
+            header,
+            section_headers,
+        })
+    }
+
+    fn slice(data: &[u8], offset: u64, size: u64) -> Option<&[u8]> {
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(usize::try_from(size).ok()?)?;
+
+        data.get(start..end)
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        let entry_size = self.header.program_header_entry_size as usize;
+        let table = Self::slice(
+            self.data,
+            self.header.program_header_offset,
+            (entry_size * self.header.program_header_number as usize) as u64,
+        )
+        .unwrap_or(&[]);
+
+        table
+            .chunks(entry_size.max(1))
+            .filter_map(|entry| ProgramHeader::try_from(entry).ok())
+    }
+
+    pub fn section_headers(&self) -> &[SectionHeader] {
+        &self.section_headers
+    }
+
+    /// The contents of a section. NOBITS sections such as .bss have no data in the file.
+    pub fn section_data(&self, section: &SectionHeader) -> Option<&'a [u8]> {
+        if section.section_type == SectionType::NoBits {
+            return Some(&[]);
+        }
+
+        Self::slice(self.data, section.offset, section.size)
+    }
+
+    fn section_names(&self) -> Option<StringTable<'a>> {
+        let section = self
+            .section_headers
+            .get(self.header.string_table_entry_number as usize)?;
+
+        Some(StringTable::new(self.section_data(section)?))
+    }
+
+    pub fn section_name(&self, section: &SectionHeader) -> Option<&'a str> {
+        self.section_names()?.get(section.name)
+    }
+
+    /// Iterates over the sections and their names
+    pub fn sections(&self) -> impl Iterator<Item = (&SectionHeader, Option<&'a str>)> + '_ {
+        let names = self.section_names();
+
+        self.section_headers
+            .iter()
+            .map(move |section| (section, names.and_then(|names| names.get(section.name))))
+    }
+
+    pub fn section_by_name(&self, name: &str) -> Option<&SectionHeader> {
+        self.sections()
+            .find(|(_, section_name)| *section_name == Some(name))
+            .map(|(section, _)| section)
+    }
+
+    /// The static symbol table (.symtab), which is missing from stripped files
+    pub fn symbol_table(&self) -> Option<SymbolTable<'a>> {
+        self.symbol_table_of_type(SectionType::SymbolTable)
+    }
+
+    /// The dynamic symbol table (.dynsym)
+    pub fn dynamic_symbol_table(&self) -> Option<SymbolTable<'a>> {
+        self.symbol_table_of_type(SectionType::DynamicSymbolTable)
+    }
+
+    fn symbol_table_of_type(&self, section_type: SectionType) -> Option<SymbolTable<'a>> {
+        let section = self
+            .section_headers
+            .iter()
+            .find(|section| section.section_type == section_type)?;
+
+        let entry_size = match section.entry_size as usize {
+            0 => Symbol::SIZE,
+            size if size >= Symbol::SIZE => size,
+            _ => return None,
+        };
+
+        let strings = self.section_headers.get(section.link as usize)?;
+
+        Some(SymbolTable {
+            data: self.section_data(section)?,
+            entry_size,
+            strings: StringTable::new(self.section_data(strings)?),
+        })
+    }
+
+    /// Resolves an address to a symbol name and offset, preferring the static symbol table
+    pub fn resolve(&self, address: u64) -> Option<(&'a str, u64)> {
+        self.symbol_table()
+            .and_then(|symbols| symbols.resolve(address))
+            .or_else(|| {
+                self.dynamic_symbol_table()
+                    .and_then(|symbols| symbols.resolve(address))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const SHSTRTAB: &[u8] = b"\0.symtab\0.strtab\0.shstrtab\0.text\0";
+    const STRTAB: &[u8] = b"\0main\0helper\0$x\0data\0";
+
+    fn symbol(name: u32, info: u8, section_index: u16, value: u64, size: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+
+        bytes.extend_from_slice(&name.to_le_bytes());
+        bytes.push(info);
+        bytes.push(0);
+        bytes.extend_from_slice(&section_index.to_le_bytes());
+        bytes.extend_from_slice(&value.to_le_bytes());
+        bytes.extend_from_slice(&size.to_le_bytes());
+
+        bytes
+    }
+
+    fn section(
+        name: u32,
+        section_type: u32,
+        address: u64,
+        offset: u64,
+        size: u64,
+        link: u32,
+        entry_size: u64,
+    ) -> Vec<u8> {
+        let mut bytes = vec![];
+
+        bytes.extend_from_slice(&name.to_le_bytes());
+        bytes.extend_from_slice(&section_type.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&address.to_le_bytes());
+        bytes.extend_from_slice(&offset.to_le_bytes());
+        bytes.extend_from_slice(&size.to_le_bytes());
+        bytes.extend_from_slice(&link.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&1u64.to_le_bytes());
+        bytes.extend_from_slice(&entry_size.to_le_bytes());
+
+        bytes
+    }
+
+    /// Builds an executable with a .text section at 0x1000 and a symbol table with two functions,
+    /// a mapping symbol and an object
+    fn sample_elf() -> Vec<u8> {
+        let symbols: Vec<u8> = [
+            symbol(0, 0, 0, 0, 0),
+            symbol(1, 0x12, 1, 0x1000, 0x20),
+            symbol(6, 0x02, 1, 0x1020, 0x10),
+            symbol(13, 0x00, 1, 0x1030, 0),
+            symbol(16, 0x11, 1, 0x2000, 8),
+        ]
+        .concat();
+
+        let strtab_offset = 64;
+        let symtab_offset = strtab_offset + STRTAB.len() as u64;
+        let shstrtab_offset = symtab_offset + symbols.len() as u64;
+        let shstrtab_size = SHSTRTAB.len() as u64;
+        let section_offset = shstrtab_offset + SHSTRTAB.len() as u64;
+
+        let mut file = vec![0; 64];
+
+        file[0..4].copy_from_slice(&[0x7f, b'E', b'L', b'F']);
+        file[4] = 2;
+        file[5] = 1;
+        file[6] = 1;
+        file[16..18].copy_from_slice(&2u16.to_le_bytes());
+        file[18..20].copy_from_slice(&ELF64Header::MACHINE_AARCH64.to_le_bytes());
+        file[24..32].copy_from_slice(&0x1000u64.to_le_bytes());
+        file[40..48].copy_from_slice(&section_offset.to_le_bytes());
+        file[52..54].copy_from_slice(&64u16.to_le_bytes());
+        file[54..56].copy_from_slice(&(ProgramHeader::SIZE as u16).to_le_bytes());
+        file[58..60].copy_from_slice(&(SectionHeader::SIZE as u16).to_le_bytes());
+        file[60..62].copy_from_slice(&5u16.to_le_bytes());
+        file[62..64].copy_from_slice(&4u16.to_le_bytes());
+
+        file.extend_from_slice(STRTAB);
+        file.extend_from_slice(&symbols);
+        file.extend_from_slice(SHSTRTAB);
+
+        file.extend(section(0, 0, 0, 0, 0, 0, 0));
+        file.extend(section(27, 1, 0x1000, 0, 0x1000, 0, 0));
+        file.extend(section(1, 2, 0, symtab_offset, symbols.len() as u64, 3, 24));
+        file.extend(section(9, 3, 0, strtab_offset, STRTAB.len() as u64, 0, 0));
+        file.extend(section(17, 3, 0, shstrtab_offset, shstrtab_size, 0, 0));
+
+        file
+    }
+
+    #[test]
+    fn test_section_names() {
+        let data = sample_elf();
+        let elf = ElfFile::parse(&data).expect("Error parsing elf");
+
+        let names: Vec<_> = elf.sections().map(|(_, name)| name).collect();
+
+        assert_eq!(
+            names,
+            [
+                Some(""),
+                Some(".text"),
+                Some(".symtab"),
+                Some(".strtab"),
+                Some(".shstrtab")
+            ]
+        );
+
+        let text = elf.section_by_name(".text").unwrap();
+
+        assert_eq!(text.address, 0x1000);
+        assert_eq!(text.section_type, SectionType::ProgramBits);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let data = sample_elf();
+        let elf = ElfFile::parse(&data).unwrap();
+        let symbols = elf.symbol_table().unwrap();
+
+        assert_eq!(symbols.len(), 5);
+        assert!(elf.dynamic_symbol_table().is_none());
+
+        let (main, name) = symbols.iter().nth(1).unwrap();
+
+        assert_eq!(name, Some("main"));
+        assert_eq!(main.symbol_type(), SymbolType::Function);
+        assert_eq!(main.binding(), SymbolBinding::Global);
+
+        let (data_symbol, _) = symbols.iter().nth(4).unwrap();
+
+        assert_eq!(data_symbol.symbol_type(), SymbolType::Object);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let data = sample_elf();
+        let elf = ElfFile::parse(&data).unwrap();
+
+        assert_eq!(elf.resolve(0x1000), Some(("main", 0)));
+        assert_eq!(elf.resolve(0x101c), Some(("main", 0x1c)));
+        assert_eq!(elf.resolve(0x1024), Some(("helper", 4)));
+        assert_eq!(elf.resolve(0x2004), Some(("data", 4)));
+
+        // Past the end of helper the closest symbol is used, skipping the $x mapping symbol
+        assert_eq!(elf.resolve(0x1034), Some(("helper", 0x14)));
+        assert_eq!(elf.resolve(0x800), None);
+    }
+
+    #[test]
+    fn test_truncated_section_table() {
+        let data = sample_elf();
+
+        assert!(ElfFile::parse(&data[..data.len() - 1]).is_err());
+    }
 }
@@ -258,8 +258,8 @@ impl<'a> FAT32Filesystem<'a> {
         let fat_offset = cluster_number * 4; // FAT32 specific
         let fat_sector_number = self.fat_start + (fat_offset / self.config.bytes_per_sector as u32);
 
-        // TODO: is this correct?
-        let fat_sector_offset = (cluster_number / 4) % self.config.bytes_per_sector as u32;
+        // Index of the entry within the sector of the table
+        let fat_sector_offset = cluster_number % (self.config.bytes_per_sector as u32 / 4);
 
         let fat_sector = FAT32FATSector::from(self.sector_device.read_sector(fat_sector_number));
 
@@ -267,60 +267,88 @@ impl<'a> FAT32Filesystem<'a> {
     }
 
     pub fn read_file(&self, file: FAT32DirectoryEntry, buffer: &mut [u8]) -> usize {
+        self.read_file_at(file, 0, buffer)
+    }
+
+    /// Reads from the file starting at the given byte offset. Returns the number of bytes read,
+    /// which is less than the length of the buffer if the end of the file is reached.
+    pub fn read_file_at(
+        &self,
+        file: FAT32DirectoryEntry,
+        offset: usize,
+        buffer: &mut [u8],
+    ) -> usize {
         if file.attributes.get_directory() == 1 {
-            0
-        } else {
-            let mut read_so_far = 0;
-            let file_size = file.file_size;
+            return 0;
+        }
+
+        let file_size = file.file_size as usize;
+
+        if offset >= file_size {
+            return 0;
+        }
+
+        let cluster_size = Sector::SECTOR_SIZE * self.config.sectors_per_cluster as usize;
+
+        let to_read = min(file_size - offset, buffer.len());
 
-            let to_read = min(file_size as usize, buffer.len());
+        // Skip the clusters that lie entirely before the offset
+        let mut current_cluster = file.first_cluster();
+        for _ in 0..offset / cluster_size {
+            if let FAT32TableEntry::Allocated(next) = self.get_fat_entry(current_cluster) {
+                current_cluster = next;
+            } else {
+                return 0;
+            }
+        }
 
-            let mut current_cluster = file.first_cluster();
-            while read_so_far < to_read {
-                let part_to_read = min(
-                    Sector::SECTOR_SIZE * self.config.sectors_per_cluster as usize,
-                    to_read - read_so_far,
-                );
+        let mut offset_in_cluster = offset % cluster_size;
+        let mut read_so_far = 0;
 
-                self.read_cluster(
-                    current_cluster,
-                    &mut buffer[read_so_far..(read_so_far + part_to_read)],
-                );
+        while read_so_far < to_read {
+            let part_to_read = min(cluster_size - offset_in_cluster, to_read - read_so_far);
 
-                read_so_far += part_to_read;
+            self.read_cluster_at(
+                current_cluster,
+                offset_in_cluster,
+                &mut buffer[read_so_far..(read_so_far + part_to_read)],
+            );
 
-                let current_fat_entry = self.get_fat_entry(current_cluster);
+            read_so_far += part_to_read;
+            offset_in_cluster = 0;
 
-                if let FAT32TableEntry::Allocated(next) = current_fat_entry {
+            if read_so_far < to_read {
+                if let FAT32TableEntry::Allocated(next) = self.get_fat_entry(current_cluster) {
                     current_cluster = next;
                 } else {
                     return read_so_far;
                 }
             }
-
-            read_so_far
         }
+
+        read_so_far
     }
 
-    fn read_cluster(&self, cluster: u32, buffer: &mut [u8]) {
+    /// Reads part of a cluster starting at a byte offset within the cluster
+    fn read_cluster_at(&self, cluster: u32, offset: usize, buffer: &mut [u8]) {
         let mut read_so_far = 0;
         let to_read = buffer.len();
 
-        let mut sector_number = self.cluster_number_to_sector_number(cluster);
-        let mut iteration = 0;
+        let mut sector_number = self.cluster_number_to_sector_number(cluster)
+            + (offset / Sector::SECTOR_SIZE) as u32;
+        let mut offset_in_sector = offset % Sector::SECTOR_SIZE;
 
         while read_so_far < to_read {
-            let amount_to_read = min(Sector::SECTOR_SIZE, to_read - read_so_far);
+            let amount_to_read = min(Sector::SECTOR_SIZE - offset_in_sector, to_read - read_so_far);
             let sector = self.sector_device.read_sector(sector_number);
 
-            for i in 0..amount_to_read {
-                buffer[(iteration as usize * Sector::SECTOR_SIZE as usize + i as usize) as usize] =
-                    sector.values[i];
-            }
+            buffer[read_so_far..read_so_far + amount_to_read].copy_from_slice(
+                &sector.values[offset_in_sector..offset_in_sector + amount_to_read],
+            );
 
             read_so_far += amount_to_read;
 
-            iteration += 1;
+            offset_in_sector = 0;
             sector_number += 1;
         }
     }
@@ -588,6 +616,10 @@ impl FAT32DirectoryEntry {
     pub fn first_cluster(&self) -> u32 {
         self.first_cluster_low_word as u32 | ((self.first_cluster_high_word as u32) << 16)
     }
+
+    pub fn file_size(&self) -> u32 {
+        self.file_size
+    }
 }
 
 impl Display for FAT32DirectoryEntry {
@@ -14,13 +14,13 @@
 extern crate alloc;
 
 #[cfg(not(test))]
-use allocator::ll_alloc::LinkedListAllocator;
+use allocator::slab_alloc::SlabAllocator;
 #[cfg(not(test))]
 use sync::SpinMutex;
 
 #[cfg(not(test))]
 #[global_allocator]
-static ALLOCATOR: SpinMutex<LinkedListAllocator> = SpinMutex::new(LinkedListAllocator::new());
+static ALLOCATOR: SpinMutex<SlabAllocator> = SpinMutex::new(SlabAllocator::new());
 
 mod aarch64;
 mod allocator;
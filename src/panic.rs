@@ -49,6 +49,8 @@ fn on_panic(info: &PanicInfo) -> ! {
         println!("No location found");
     }
 
+    PLATFORM.print_backtrace(None, crate::aarch64::backtrace::frame_pointer());
+
     loop {}
 }
 
@@ -73,6 +75,7 @@ fn on_alloc_error(layout: Layout) -> ! {
 
     println!("{} allocations, {} frees", stats.allocs, stats.frees);
     println!("{} bytes in {} blocks", stats.free_space, stats.blocks);
+    println!("{} slab pages", stats.slab_pages);
 
     loop {}
 }
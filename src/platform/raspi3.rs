@@ -1,6 +1,9 @@
 //! Rasperry Pi 3 platform specific implementations
 
+pub mod barrier;
+pub mod channel;
 pub mod clock;
+pub mod condvar;
 pub mod emmc;
 pub mod framebuffer;
 pub mod gpio;
@@ -8,18 +11,25 @@ pub mod hardware_config;
 pub mod interrupt;
 pub mod kernel;
 pub mod kernel_object;
+pub mod loader;
 pub mod mailbox;
 pub mod mailbox_property;
 pub mod mini_uart;
 pub mod mmio;
 pub mod page_table;
+pub mod pipe;
 pub mod platform_devices;
 pub mod power;
 pub mod programs;
+pub mod run_queue;
+pub mod rwlock;
 pub mod semaphore;
+pub mod smp;
 #[cfg(not(test))]
 pub mod start;
 pub mod thread;
 pub mod timer;
+pub mod user_memory;
+pub mod wait_queue;
 
 mod exception;
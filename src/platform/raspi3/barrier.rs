@@ -0,0 +1,45 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::aarch64::cpu;
+
+/// Lets a fixed number of threads wait for each other, for workers that run in phases. Threads
+/// that arrive early sleep in the kernel until the last one arrives.
+#[derive(Debug)]
+pub struct Barrier {
+    threads: u64,
+    arrived: AtomicU64,
+    /// Counts the completed phases, waiters sleep until it changes
+    generation: AtomicU64,
+}
+
+impl Barrier {
+    pub const fn new(threads: u64) -> Self {
+        Self {
+            threads,
+            arrived: AtomicU64::new(0),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Waits until all threads have arrived. Returns true for exactly one thread of each phase,
+    /// the last one to arrive. The barrier can be used again right away.
+    pub fn wait(&self) -> bool {
+        let generation = self.generation.load(Ordering::SeqCst);
+
+        if self.arrived.fetch_add(1, Ordering::SeqCst) + 1 == self.threads {
+            // Nobody can arrive for the next phase before the generation changes
+            self.arrived.store(0, Ordering::SeqCst);
+            self.generation.fetch_add(1, Ordering::SeqCst);
+
+            cpu::futex_wake(&self.generation, usize::MAX);
+
+            return true;
+        }
+
+        while self.generation.load(Ordering::SeqCst) == generation {
+            cpu::futex_wait(&self.generation, generation);
+        }
+
+        false
+    }
+}
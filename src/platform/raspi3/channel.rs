@@ -0,0 +1,158 @@
+//! Bounded message channels between threads
+//!
+//! A channel is opened by name with `chan:<name>:r` for a read end or `chan:<name>:w` for a write
+//! end, any number of ends of both kinds can be open. The first end to be opened creates the
+//! channel, `chan:<name>:w:<capacity>` sets the number of messages it holds instead of
+//! [DEFAULT_CAPACITY]. Each write queues one message and each read takes one, cut off at the size
+//! of the buffer. Reads wait while the channel is empty and writes wait while it is full.
+//!
+//! Once all write ends that were opened have been closed, reads of an empty channel return 0 to
+//! signal the end of the stream. Likewise writes return 0 once all read ends have been closed.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::kernel_object::{KernelObject, Transfer};
+use super::wait_queue::WaitQueueID;
+use crate::aarch64::interrupt::IRQLock;
+
+pub const DEFAULT_CAPACITY: usize = 16;
+
+#[derive(Debug)]
+struct ChannelState {
+    messages: VecDeque<Vec<u8>>,
+    readers: usize,
+    writers: usize,
+    /// Whether ends of the kind have been opened, before that the other side waits for them
+    had_readers: bool,
+    had_writers: bool,
+}
+
+#[derive(Debug)]
+pub struct Channel {
+    capacity: usize,
+    state: IRQLock<ChannelState>,
+}
+
+impl Channel {
+    pub fn new(capacity: usize) -> Arc<Self> {
+        Arc::new(Self {
+            capacity: capacity.max(1),
+            state: IRQLock::new(ChannelState {
+                messages: VecDeque::new(),
+                readers: 0,
+                writers: 0,
+                had_readers: false,
+                had_writers: false,
+            }),
+        })
+    }
+
+    /// Threads waiting for a message
+    fn readers_queue(self: &Arc<Self>) -> WaitQueueID {
+        WaitQueueID::ObjectReaders(Arc::as_ptr(self) as usize)
+    }
+
+    /// Threads waiting for space in the channel
+    fn writers_queue(self: &Arc<Self>) -> WaitQueueID {
+        WaitQueueID::ObjectWriters(Arc::as_ptr(self) as usize)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelEndKind {
+    Read,
+    Write,
+}
+
+/// A handle to one end of a channel
+#[derive(Debug)]
+pub struct ChannelEnd {
+    channel: Arc<Channel>,
+    kind: ChannelEndKind,
+}
+
+impl ChannelEnd {
+    pub fn new(channel: Arc<Channel>, kind: ChannelEndKind) -> Self {
+        {
+            let mut state = channel.state.lock();
+
+            match kind {
+                ChannelEndKind::Read => {
+                    state.readers += 1;
+                    state.had_readers = true;
+                }
+                ChannelEndKind::Write => {
+                    state.writers += 1;
+                    state.had_writers = true;
+                }
+            }
+        }
+
+        Self { channel, kind }
+    }
+}
+
+impl KernelObject for ChannelEnd {
+    fn read(&self, buffer: &mut [u8]) -> Transfer {
+        if self.kind != ChannelEndKind::Read {
+            return Transfer::Done(0, None);
+        }
+
+        let mut state = self.channel.state.lock();
+
+        match state.messages.pop_front() {
+            Some(message) => {
+                let length = message.len().min(buffer.len());
+
+                buffer[..length].copy_from_slice(&message[..length]);
+
+                Transfer::Done(length, Some(self.channel.writers_queue()))
+            }
+            None if state.had_writers && state.writers == 0 => Transfer::Done(0, None),
+            None => Transfer::Blocked(self.channel.readers_queue()),
+        }
+    }
+
+    fn write(&self, buffer: &mut [u8]) -> Transfer {
+        // An empty message would look like the end of the stream
+        if self.kind != ChannelEndKind::Write || buffer.is_empty() {
+            return Transfer::Done(0, None);
+        }
+
+        let mut state = self.channel.state.lock();
+
+        if state.had_readers && state.readers == 0 {
+            Transfer::Done(0, None)
+        } else if state.messages.len() < self.channel.capacity {
+            state.messages.push_back(buffer.to_vec());
+
+            Transfer::Done(buffer.len(), Some(self.channel.readers_queue()))
+        } else {
+            Transfer::Blocked(self.channel.writers_queue())
+        }
+    }
+
+    /// The last end of a kind to close wakes the other side, which sees the end of the stream
+    fn close(&self) -> Option<WaitQueueID> {
+        let state = self.channel.state.lock();
+
+        match self.kind {
+            ChannelEndKind::Read if state.readers == 1 => Some(self.channel.writers_queue()),
+            ChannelEndKind::Write if state.writers == 1 => Some(self.channel.readers_queue()),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for ChannelEnd {
+    fn drop(&mut self) {
+        let mut state = self.channel.state.lock();
+
+        match self.kind {
+            ChannelEndKind::Read => state.readers -= 1,
+            ChannelEndKind::Write => state.writers -= 1,
+        }
+    }
+}
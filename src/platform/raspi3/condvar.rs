@@ -0,0 +1,59 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::semaphore::SemMutexGuard;
+use crate::aarch64::cpu;
+
+/// A condition variable for threads that hold a [SemMutex](super::semaphore::SemMutex). Waiting
+/// threads sleep in the kernel until they are notified.
+#[derive(Debug)]
+pub struct Condvar {
+    /// Changed by every notification, waiters only go to sleep if it has not changed since they
+    /// released the mutex
+    sequence: AtomicU64,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Releases the mutex, sleeps until notified and locks the mutex again. Wake ups can be
+    /// spurious, so the condition has to be checked again, see [Self::wait_while].
+    pub fn wait<'a, T>(&self, guard: SemMutexGuard<'a, T>) -> SemMutexGuard<'a, T> {
+        let sequence = self.sequence.load(Ordering::SeqCst);
+        let mutex = SemMutexGuard::mutex(&guard);
+
+        drop(guard);
+
+        cpu::futex_wait(&self.sequence, sequence);
+
+        mutex.lock()
+    }
+
+    /// Waits for as long as the condition holds
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: SemMutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> SemMutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        self.sequence.fetch_add(1, Ordering::SeqCst);
+
+        cpu::futex_wake(&self.sequence, 1);
+    }
+
+    pub fn notify_all(&self) {
+        self.sequence.fetch_add(1, Ordering::SeqCst);
+
+        cpu::futex_wake(&self.sequence, usize::MAX);
+    }
+}
@@ -4,7 +4,9 @@ use core::arch::global_asm;
 use crate::{
     aarch64::{
         cpu,
-        registers::{ExceptionLinkRegister, ExceptionSyndromeRegister, FaultAddressRegister},
+        registers::{
+            ExceptionClass, ExceptionLinkRegister, ExceptionSyndromeRegister, FaultAddressRegister,
+        },
     },
     bitfield, elf,
     platform::platform_devices::{get_platform, PLATFORM},
@@ -51,6 +53,19 @@ pub extern "C" fn handle_exception(
 
     if exception_type == ExceptionType::Interrupt {
         platform.handle_interrupt();
+    } else if matches!(
+        exception_source,
+        ExceptionSource::LowerEL64 | ExceptionSource::LowerEL32
+    ) {
+        let esr = ExceptionSyndromeRegister::read_to_buffer();
+        let far = FaultAddressRegister::read_to_buffer().value();
+
+        println!(
+            "Received Exception Type {:?} from {:?}",
+            exception_type, exception_source
+        );
+
+        PLATFORM.handle_user_fault(esr, far);
     } else {
         println!(
             "Received Exception Type {:?} from {:?}",
@@ -66,12 +81,15 @@ pub extern "C" fn handle_exception(
         let far = FaultAddressRegister::read_to_buffer().value();
         let elr = ExceptionLinkRegister::read_to_buffer().value();
 
+        println!("{}", ExceptionClass::read());
         println!("elr: {:#x}", elr);
         println!("esr: {:#x}", esr);
         println!("far: {:#x}", far);
 
         println!("{:?}", frame);
 
+        PLATFORM.print_backtrace(Some(frame.elr), frame.frame_pointer());
+
         loop {}
     }
 }
@@ -86,6 +104,19 @@ pub struct InterruptFrame {
     pub fpsr: u64,
 }
 
+impl InterruptFrame {
+    /// Whether the exception was taken from EL0, based on the mode bits of the saved spsr
+    pub fn is_from_user(&self) -> bool {
+        self.spsr & 0b1111 == 0
+    }
+
+    /// The interrupted frame pointer. push_frame leaves a gap before x16, so x29 is saved in the
+    /// last of the general purpose slots.
+    pub fn frame_pointer(&self) -> u64 {
+        self.regs[31]
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn handle_synchronous_exception(
     arg1: usize,
@@ -118,9 +149,20 @@ pub extern "C" fn handle_synchronous_exception(
 
         // println!("arg1: {}", arg1);
 
-        PLATFORM.handle_syscall(syscall_number, [arg1, arg2, arg3]);
+        // x3 holds the frame pointer, so the fourth argument is taken from the saved registers
+        PLATFORM.handle_syscall(
+            syscall_number,
+            [arg1, arg2, arg3, frame.regs[3] as usize],
+        );
+    } else if frame.is_from_user() {
+        // A bad user program only takes down its own thread
+        PLATFORM.handle_user_fault(esr, far.value());
     } else {
-        println!("Received syncronous exception: {:#x}", esr.value());
+        println!(
+            "Received syncronous exception: {} ({:#x})",
+            ExceptionClass::decode(esr, far.value()),
+            esr.value()
+        );
         println!("FAR: {:#x}", far.value());
         println!("ELR: {:#x}", elr.value());
 
@@ -131,6 +173,8 @@ pub extern "C" fn handle_synchronous_exception(
 
         println!("{:?}", frame);
 
+        PLATFORM.print_backtrace(Some(frame.elr), frame.frame_pointer());
+
         loop {}
     }
 }
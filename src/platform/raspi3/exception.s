@@ -58,6 +58,7 @@ _exception_vector:
     bl pop_frame
     ldr lr, [sp], #16
     //msr daifclr, 0b10 // Enable Interrupts
+    eret
 .org 0x480
     call_handler handle_exception 2 1
 .org 0x500
@@ -14,6 +14,7 @@ use crate::platform::raspi3::mailbox_property::{
     MailboxInstruction,
     MailboxBufferSlice
 };
+use crate::aarch64::cpu;
 use crate::volatile::Volatile;
 
 pub struct FrameBuffer<'a> {
@@ -79,6 +80,11 @@ impl<'a> FrameBuffer<'a> {
     pub fn get_config(&self) -> FrameBufferConfig {
         self.config
     }
+
+    /// Writes the pixels still held in the data caches to memory, where the GPU reads them
+    pub fn flush(&self) {
+        cpu::clean_and_invalidate_data_cache(self.buffer.as_ptr() as usize, self.buffer.len() * core::mem::size_of::<u32>());
+    }
 }
 
 pub trait FramebufferProperty {
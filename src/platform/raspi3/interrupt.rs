@@ -56,6 +56,49 @@ impl InterruptRegisters {
     }
 }
 
+/// The per-core interrupt routing of the BCM2836 family. Interrupts of the legacy controller above
+/// only reach core 0, every core has its own timer and source registers.
+#[repr(C)]
+#[derive(Debug)]
+pub struct LocalInterruptRegisters {
+    control: Volatile<u32>,
+    _reserved_0: [Volatile<u32>; 2],
+    gpu_interrupt_routing: Volatile<u32>,
+    _reserved_1: [Volatile<u32>; 12],
+    core_timer_control: [Volatile<CoreTimerControl>; 4],
+    mailbox_control: [Volatile<MailboxControl>; 4],
+    irq_source: [Volatile<LocalIRQSource>; 4],
+    fiq_source: [Volatile<u32>; 4],
+    /// Writing sets bits of the mailboxes of each core
+    mailbox_set: [[Volatile<u32>; 4]; 4],
+    /// Reads the mailboxes of each core, writing clears bits
+    mailbox_clear: [[Volatile<u32>; 4]; 4],
+}
+
+impl LocalInterruptRegisters {
+    /// Routes the physical generic timer of the core to its IRQ line
+    pub fn enable_core_timer_interrupt(&mut self, core: usize) {
+        self.core_timer_control[core].map(|control| control.set_physical_timer_irq(1));
+    }
+
+    /// Lets other cores interrupt the core through its first mailbox
+    pub fn enable_mailbox_interrupt(&mut self, core: usize) {
+        self.mailbox_control[core].map(|control| control.set_mailbox_0_irq(1));
+    }
+
+    pub fn send_wake_up(&mut self, core: usize) {
+        self.mailbox_set[core][0].set(1);
+    }
+
+    pub fn clear_wake_up(&mut self, core: usize) {
+        self.mailbox_clear[core][0].set(u32::MAX);
+    }
+
+    pub fn get_interrupt_source(&self, core: usize) -> LocalIRQSource {
+        self.irq_source[core].get()
+    }
+}
+
 pub struct InterruptController<'a> {
     registers: &'a mut InterruptRegisters,
 }
@@ -112,3 +155,23 @@ bitfield! {
         arm_timer: 0-0
     }
 }
+
+bitfield! {
+    CoreTimerControl(u32) {
+        physical_timer_irq: 1-1
+    }
+}
+
+bitfield! {
+    MailboxControl(u32) {
+        mailbox_0_irq: 0-0
+    }
+}
+
+bitfield! {
+    LocalIRQSource(u32) {
+        physical_timer: 1-1,
+        mailbox_0: 4-4,
+        gpu: 8-8
+    }
+}
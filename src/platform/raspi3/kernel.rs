@@ -1,9 +1,11 @@
 use super::thread::ThreadID;
+use alloc::collections::BTreeMap;
 use alloc::rc::Rc;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use core::{
     cell::{Ref, RefCell},
+    mem::{self, MaybeUninit},
     slice, str,
     time::Duration,
 };
@@ -13,11 +15,13 @@ use crate::{
         cpu,
         interrupt::IRQLock,
         mmu,
+        registers::{ExceptionClass, ExceptionSyndromeRegister},
         syscall::{Syscall, SyscallArgs},
     },
     allocator::{
+        buddy_alloc,
         id_allocator::IDAllocator,
-        page_allocator::{self, Page, PageAllocator, PageRef, PAGE_SIZE},
+        page_allocator::{self, Page, PageRef, PAGE_SIZE},
     },
     elf::{ELF64Header, ProgramHeader},
     filesystem::{
@@ -25,13 +29,18 @@ use crate::{
         fat32::{FAT32DirectoryEntry, FAT32Filesystem},
     },
     platform::{
+        channel::{self, Channel, ChannelEnd, ChannelEndKind},
         framebuffer::FrameBuffer,
-        kernel_object::{FileObject, Stdio},
+        kernel_object::{FileObject, InheritedHandles, Stdio},
         page_table::PageTable,
+        pipe::{self, Pipe},
         platform_devices::{get_platform, PLATFORM},
         raspi3::exception::InterruptFrame,
-        thread::{Scheduler, Thread, ThreadStatus},
+        loader::{ElfLoader, ExecError, ProgramArguments},
+        thread::{self, Scheduler, Thread, ThreadInfo, ThreadStats, ThreadStatus},
+        user_memory::UserHeap,
     },
+    println,
 };
 
 use alloc::boxed::Box;
@@ -39,53 +48,67 @@ use alloc::string::String;
 
 use super::kernel_object::ObjectHandle;
 
+/// Length of a time slice in microseconds
 pub const TICK: u32 = 1_000;
 
+/// Shortest delay in microseconds that the wake up timer is set to, so that it is never set to a
+/// time that passes before the timer is written
+pub const MIN_WAKE_UP_DELAY: u64 = 10;
+
 pub struct Kernel<'a> {
     pub scheduler: Scheduler<'a>,
-    pub page_allocator: IRQLock<PageAllocator<'a>>,
     pub thread_id_allocator: IDAllocator,
     pub object_id_allocator: IDAllocator,
     filesystem: Arc<IRQLock<FAT32Filesystem<'a>>>, // TODO: should this be here or on the platform?
+    /// Channels by name, they are freed when their last end is closed
+    channels: BTreeMap<String, Weak<Channel>>,
 }
 
 impl<'a> Kernel<'a> {
-    pub fn with_page_allocator_and_filesystem(
-        page_allocator: IRQLock<PageAllocator<'a>>,
-        filesystem: IRQLock<FAT32Filesystem<'a>>,
-    ) -> Self {
+    /// Pages are taken from `PAGE_ALLOCATOR`, which has to be set up before
+    pub fn with_filesystem(filesystem: IRQLock<FAT32Filesystem<'a>>) -> Self {
         Self {
             scheduler: Scheduler::new(),
-            page_allocator,
             thread_id_allocator: IDAllocator::new(),
             object_id_allocator: IDAllocator::new(),
             filesystem: Arc::new(filesystem),
+            channels: BTreeMap::new(),
         }
     }
 
     pub fn allocate_page(&mut self) -> PageRef {
-        self.page_allocator
-            .lock()
-            .allocate_page()
-            .expect("Error allocationg page")
+        self.allocate_pages(0).expect("Error allocationg page")
+    }
+
+    /// Allocates 2^order contiguous pages, aligned to their size
+    pub fn allocate_pages(&mut self, order: usize) -> Option<PageRef> {
+        buddy_alloc::allocate_pages(order)
+    }
+
+    /// Frees the block of pages starting at the address
+    pub fn free_page(&mut self, address: usize) {
+        buddy_alloc::free_pages(address);
     }
 
     pub fn create_thread(&mut self, entry: usize, args: SyscallArgs) {
+        // The handle list of an EL0 caller would be read from unchecked user memory, so threads
+        // created from EL0 start without handles
+        let from_user = self.scheduler.current_thread().saved_frame().is_from_user();
+
         let page_ref = self
-            .page_allocator
-            .lock()
-            .allocate_page()
-            .expect("Unable to Allocate Page");
+            .allocate_pages(thread::KERNEL_STACK_ORDER)
+            .expect("Unable to allocate kernel stack");
 
         let stack_pointer;
         let name;
+        let inherited;
 
         unsafe {
             let page = page_ref.page;
 
             let page64 = page as *mut u64;
 
-            let mut sp = PAGE_SIZE / 8;
+            let mut sp = thread::KERNEL_STACK_SIZE / 8;
 
             sp -= 106; // TODO: use size_of instead of a magic number
 
@@ -99,28 +122,46 @@ impl<'a> Kernel<'a> {
             stack_pointer = IRQLock::new(page64.offset(sp as isize) as *const u64);
 
             name = String::from(&*(args[1] as *mut String));
+            inherited = if from_user {
+                InheritedHandles::None
+            } else {
+                *(args[3] as *const InheritedHandles)
+            };
         }
 
+        let objects = self.scheduler.inherited_objects(inherited);
+
         let id = self.thread_id_allocator.allocate_id();
 
-        let kernel_table = IRQLock::new(*self.scheduler.current_thread.kernel_table.lock());
+        let kernel_table = IRQLock::new(*self.scheduler.current_thread().kernel_table.lock());
 
         self.scheduler.add_thread(Thread {
             stack_pointer,
-            parent: Some(self.scheduler.current_thread.clone()),
+            kernel_stack: Some(page_ref.page as usize),
+            parent: Some(Arc::downgrade(self.scheduler.current_thread())),
             status: IRQLock::new(ThreadStatus::Ready),
+            priority: IRQLock::new(thread::DEFAULT_PRIORITY),
             name,
             id,
             children: IRQLock::new(vec![]),
-            objects: IRQLock::new(vec![]),
+            objects: IRQLock::new(objects),
             kernel_table, // Currently all kernel threads have the same mapping
             user_table: IRQLock::new(PageTable::new_unmapped()),
+            heap: IRQLock::new(UserHeap::default()),
+            stats: IRQLock::new(ThreadStats::default()),
+            pending_kill: IRQLock::new(None),
         });
 
         self.scheduler.set_current_thread_return(id);
     }
 
     pub fn handle_syscall(&mut self, number: usize, args: SyscallArgs) {
+        self.scheduler.reap_exited_threads();
+
+        if self.scheduler.exit_if_killed() {
+            return;
+        }
+
         let syscall = Syscall::from_u64(number as u64).expect("Invalid Syscall Number");
         match syscall {
             Syscall::Thread => self.create_thread(args[0], args),
@@ -140,28 +181,172 @@ impl<'a> Kernel<'a> {
             Syscall::Write => self.write_object(args[0] as u64, unsafe {
                 slice::from_raw_parts_mut(args[1] as *mut u8, args[2])
             }),
-            _ => panic!("Unsupported Syscall"),
+            Syscall::Exec => {
+                let program_name = if args[0] != 0
+                    && self.scheduler.current_thread().can_access(
+                        args[0] as u64,
+                        args[1] as u64,
+                        false,
+                    ) {
+                    let bytes = unsafe { slice::from_raw_parts(args[0] as *const u8, args[1]) };
+
+                    str::from_utf8(bytes).map_err(|_| ExecError::FileNotFound)
+                } else {
+                    Err(ExecError::InvalidAddress)
+                };
+
+                let arguments = unsafe {
+                    ProgramArguments::from_user(
+                        self.scheduler.current_thread(),
+                        args[2] as *const *const u8,
+                        args[3] as *const *const u8,
+                    )
+                };
+
+                match program_name.and_then(|name| Ok((name, arguments?))) {
+                    Ok((program_name, arguments)) => {
+                        self.exec_current_thread(program_name, &arguments)
+                    }
+                    Err(error) => self.scheduler.set_current_thread_return(error as u64),
+                }
+            }
+            Syscall::GetPriority => self.scheduler.get_priority(args[0] as ThreadID),
+            Syscall::SetPriority => self
+                .scheduler
+                .set_priority(args[0] as ThreadID, args[1] as u64),
+            Syscall::Kill => self
+                .scheduler
+                .kill_thread(args[0] as ThreadID, args[1] as u64),
+            Syscall::FutexWait => self.scheduler.futex_wait(args[0], args[1] as u64),
+            Syscall::FutexWake => self.scheduler.futex_wake(args[0], args[1]),
+            Syscall::Dup => {
+                let new_handle = self.object_id_allocator.allocate_id();
+
+                self.scheduler
+                    .duplicate_object(args[0] as ObjectHandle, new_handle)
+            }
+            Syscall::Pipe => self.create_pipe(args[0] as *mut [ObjectHandle; 2]),
+            Syscall::Brk => self.scheduler.set_program_break(args[0] as u64),
+            Syscall::Mmap => {
+                self.scheduler
+                    .map_anonymous(args[0] as u64, args[1] as u64, args[2] as u64)
+            }
+            Syscall::Munmap => self
+                .scheduler
+                .unmap_anonymous(args[0] as u64, args[1] as u64),
+            Syscall::ListThreads => {
+                let accessible = args[0].is_multiple_of(mem::align_of::<ThreadInfo>())
+                    && args[1]
+                        .checked_mul(mem::size_of::<ThreadInfo>())
+                        .is_some_and(|size| {
+                            self.scheduler.current_thread().can_access(
+                                args[0] as u64,
+                                size as u64,
+                                true,
+                            )
+                        });
+
+                // A buffer the caller cannot write to is treated as empty
+                let buffer: &mut [MaybeUninit<ThreadInfo>] = if accessible && args[1] != 0 {
+                    unsafe {
+                        slice::from_raw_parts_mut(args[0] as *mut MaybeUninit<ThreadInfo>, args[1])
+                    }
+                } else {
+                    &mut []
+                };
+
+                self.scheduler.list_threads(buffer)
+            }
         }
     }
 
-    pub fn exec(&mut self, program_name: &str) {
-        self.scheduler.current_thread.exec(program_name);
+    pub fn exec(&mut self, program_name: &str, arguments: &ProgramArguments) {
+        let program = FileObject::from_entry(
+            self.find_file(program_name)
+                .expect("Unable to find program to execute"),
+        );
+
+        self.scheduler.current_thread().exec(&program, arguments);
+    }
+
+    /// Replaces the current thread's user image with the given program. On success the thread
+    /// returns from the syscall into the new program, otherwise it receives an [ExecError] code.
+    pub fn exec_current_thread(&mut self, program_name: &str, arguments: &ProgramArguments) {
+        let program = match self.find_file(program_name) {
+            Some(entry) => FileObject::from_entry(entry),
+            None => {
+                self.scheduler
+                    .set_current_thread_return(ExecError::FileNotFound as u64);
+                return;
+            }
+        };
+
+        let loader = match ElfLoader::new(&program)
+            .and_then(|loader| loader.validate_arguments(arguments).map(|_| loader))
+        {
+            Ok(loader) => loader,
+            Err(error) => {
+                self.scheduler.set_current_thread_return(error as u64);
+                return;
+            }
+        };
+
+        match self
+            .scheduler
+            .current_thread()
+            .load_program(&loader, arguments)
+        {
+            Ok(entry) => self.scheduler.current_thread().enter_user_on_return(&entry),
+            // The old image has already been torn down so there is nothing to return to
+            Err(error) => self.exit_current_thread(error as u64),
+        }
+    }
+
+    fn find_file(&self, name: &str) -> Option<FAT32DirectoryEntry> {
+        let mut split = name.split(":");
+
+        if split.next() != Some("file") {
+            return None;
+        }
+
+        let path = split.next()?;
+
+        self.filesystem.lock().search_item(path)
+    }
+
+    /// Adds the calling core to the scheduler, see [Scheduler::add_core]
+    pub fn add_core(&mut self) {
+        self.scheduler.add_core();
     }
 
     pub fn tick(&mut self) {
+        self.scheduler.reap_exited_threads();
         self.scheduler.wake_sleeping();
-        self.scheduler.schedule();
+
+        if !self.scheduler.exit_if_killed() {
+            self.scheduler.schedule();
+        }
     }
 
-    pub fn get_return_thread(&mut self) -> Arc<Thread<'a>> {
-        self.scheduler.choose_thread()
+    /// Programs the timers for what the calling core runs next. Threads run for a time slice and
+    /// the system timer fires when the next sleeping thread is due, idle cores are not interrupted
+    /// otherwise.
+    pub fn update_timers(&mut self) {
+        if let Some(running) = self.scheduler.take_slice_start() {
+            PLATFORM.set_slice_timer(running);
+        }
+
+        if let Some(wake_time) = self.scheduler.get_next_thread_wakeup() {
+            PLATFORM.set_wake_up_timer(wake_time);
+        }
     }
 
-    pub fn return_from_exception(&self) {
-        self.scheduler.return_to_current();
+    pub fn get_return_thread(&mut self) -> Arc<Thread<'a>> {
+        self.scheduler.choose_thread()
     }
 
     pub fn save_current_frame(&mut self, frame: &mut InterruptFrame) {
+        self.scheduler.account_time(true);
         self.scheduler
             .set_current_stack_pointer(frame as *const InterruptFrame as *const u64);
     }
@@ -170,16 +355,32 @@ impl<'a> Kernel<'a> {
         self.scheduler.exit_current_thread(code);
     }
 
+    /// Terminates the current thread after an exception it caused at EL0. Joiners are woken with
+    /// an exit code that marks the thread as faulted.
+    pub fn kill_current_thread(
+        &mut self,
+        syndrome: ExceptionSyndromeRegister::RegisterBuffer,
+        fault_address: usize,
+    ) {
+        let thread = Arc::clone(self.scheduler.current_thread());
+
+        println!(
+            "Killing thread {} ({}): {} at elr {:#x}",
+            thread.name,
+            thread.id,
+            ExceptionClass::decode(syndrome, fault_address),
+            thread.saved_frame().elr,
+        );
+
+        self.exit_current_thread(thread::fault_exit_code(syndrome.value() as u64));
+    }
+
     pub fn delay_current_thread(&mut self, delay: u64) {
         // TODO: what is wake up time is before the current time because of the time the computations take?
         let current_time = PLATFORM.get_timer().get_micros();
         let delay_end = current_time + delay;
 
         self.scheduler.delay_current_thread(delay_end);
-
-        //let new_timeout = self.scheduler.get_next_thread_wakeup().unwrap() - current_time;
-
-        //PLATFORM.get_timer().set_timeout(new_timeout as u32);
     }
 
     pub fn join_current_thread(&mut self, thread_id: ThreadID) {
@@ -191,14 +392,11 @@ impl<'a> Kernel<'a> {
         let prefix = split.next().unwrap();
 
         if prefix == "file" {
-            let path = split.next().unwrap();
-            let entry = self.filesystem.lock().search_item(path);
-
-            if let Some(entry) = entry {
+            if let Some(entry) = self.find_file(name) {
                 let id = self.object_id_allocator.allocate_id();
 
                 self.scheduler
-                    .add_object_to_current_thread(Box::new(FileObject::from_entry(entry)), id);
+                    .add_object_to_current_thread(Arc::new(FileObject::from_entry(entry)), id);
 
                 self.scheduler.set_current_thread_return(id);
             } else {
@@ -207,11 +405,89 @@ impl<'a> Kernel<'a> {
         } else if prefix == "stdio" {
             let id = self.object_id_allocator.allocate_id();
             self.scheduler
-                .add_object_to_current_thread(Box::new(Stdio::new()), id);
+                .add_object_to_current_thread(Arc::new(Stdio::new()), id);
             self.scheduler.set_current_thread_return(id);
+        } else if prefix == "chan" {
+            match self.open_channel(name) {
+                Some(end) => {
+                    let id = self.object_id_allocator.allocate_id();
+
+                    self.scheduler
+                        .add_object_to_current_thread(Arc::new(end), id);
+                    self.scheduler.set_current_thread_return(id);
+                }
+                None => self.scheduler.set_current_thread_return(0),
+            }
         }
     }
 
+    /// Opens an end of a channel by a name of the form chan:name:r|w[:capacity], creating the
+    /// channel if it is not open yet. See the [channel](super::channel) module.
+    fn open_channel(&mut self, name: &str) -> Option<ChannelEnd> {
+        let mut split = name.split(":").skip(1);
+
+        let channel_name = split.next().filter(|name| !name.is_empty())?;
+
+        let kind = match split.next()? {
+            "r" => ChannelEndKind::Read,
+            "w" => ChannelEndKind::Write,
+            _ => return None,
+        };
+
+        let capacity = match split.next() {
+            Some(capacity) => capacity.parse().ok()?,
+            None => channel::DEFAULT_CAPACITY,
+        };
+
+        self.channels
+            .retain(|_, channel| channel.strong_count() > 0);
+
+        let channel = match self.channels.get(channel_name).and_then(Weak::upgrade) {
+            Some(channel) => channel,
+            None => {
+                let channel = Channel::new(capacity);
+
+                self.channels
+                    .insert(String::from(channel_name), Arc::downgrade(&channel));
+
+                channel
+            }
+        };
+
+        Some(ChannelEnd::new(channel, kind))
+    }
+
+    /// Creates a pipe and stores the handles of its read end and write end at the address.
+    /// Returns 0, or [PIPE_ERROR](pipe::PIPE_ERROR) for a null or misaligned address or one the
+    /// caller cannot write to.
+    pub fn create_pipe(&mut self, handles: *mut [ObjectHandle; 2]) {
+        if handles.is_null()
+            || !handles.is_aligned()
+            || !self.scheduler.current_thread().can_access(
+                handles as u64,
+                mem::size_of::<[ObjectHandle; 2]>() as u64,
+                true,
+            )
+        {
+            self.scheduler.set_current_thread_return(pipe::PIPE_ERROR);
+            return;
+        }
+
+        let (reader, writer) = Pipe::create();
+
+        let read_handle = self.object_id_allocator.allocate_id();
+        let write_handle = self.object_id_allocator.allocate_id();
+
+        self.scheduler
+            .add_object_to_current_thread(Arc::new(reader), read_handle);
+        self.scheduler
+            .add_object_to_current_thread(Arc::new(writer), write_handle);
+
+        unsafe { handles.write([read_handle, write_handle]) };
+
+        self.scheduler.set_current_thread_return(0);
+    }
+
     pub fn read_object(&mut self, handle: ObjectHandle, buffer: &mut [u8]) {
         self.scheduler.read(handle, buffer);
     }
@@ -223,4 +499,8 @@ impl<'a> Kernel<'a> {
     pub fn read(&self, entry: FAT32DirectoryEntry, buffer: &mut [u8]) -> usize {
         self.filesystem.lock().read_file(entry, buffer)
     }
+
+    pub fn read_at(&self, entry: FAT32DirectoryEntry, offset: usize, buffer: &mut [u8]) -> usize {
+        self.filesystem.lock().read_file_at(entry, offset, buffer)
+    }
 }
@@ -7,15 +7,44 @@ use crate::{
     platform::platform_devices::{get_platform, PLATFORM},
 };
 
+use super::wait_queue::WaitQueueID;
+
 pub type ObjectHandle = u64;
 
+/// The handles a new thread starts with. They keep their values, so the child refers to the
+/// objects the same way as its parent.
+#[derive(Copy, Clone, Debug)]
+pub enum InheritedHandles<'a> {
+    None,
+    All,
+    /// Only the listed handles, handles the parent does not have are left out
+    Only(&'a [ObjectHandle]),
+}
+
+/// The outcome of a read or write on a kernel object
+#[derive(Copy, Clone, Debug)]
+pub enum Transfer {
+    /// The number of bytes that were transferred, and a wait queue whose threads may be able to
+    /// continue now
+    Done(usize, Option<WaitQueueID>),
+    /// Nothing can be transferred before another thread acts on the object. The calling thread
+    /// waits in the queue and then makes the syscall again.
+    Blocked(WaitQueueID),
+}
+
 pub trait KernelObject: Debug {
-    fn read(&self, _: &mut [u8]) -> usize {
-        0
+    fn read(&self, _: &mut [u8]) -> Transfer {
+        Transfer::Done(0, None)
     }
 
-    fn write(&self, _: &mut [u8]) -> usize {
-        0
+    fn write(&self, _: &mut [u8]) -> Transfer {
+        Transfer::Done(0, None)
+    }
+
+    /// Called when the last handle to the object is closed. Returns a wait queue whose threads are
+    /// woken once the object has been dropped.
+    fn close(&self) -> Option<WaitQueueID> {
+        None
     }
 }
 
@@ -28,11 +57,20 @@ impl FileObject {
     pub fn from_entry(fat_entry: FAT32DirectoryEntry) -> Self {
         Self { fat_entry }
     }
+
+    /// Reads from the file starting at the given byte offset
+    pub fn read_at(&self, offset: usize, buffer: &mut [u8]) -> usize {
+        get_platform().read_at(self.fat_entry, offset, buffer)
+    }
+
+    pub fn size(&self) -> usize {
+        self.fat_entry.file_size() as usize
+    }
 }
 
 impl KernelObject for FileObject {
-    fn read(&self, buffer: &mut [u8]) -> usize {
-        get_platform().read(self.fat_entry, buffer)
+    fn read(&self, buffer: &mut [u8]) -> Transfer {
+        Transfer::Done(get_platform().read(self.fat_entry, buffer), None)
     }
 }
 
@@ -46,11 +84,11 @@ impl Stdio {
 }
 
 impl KernelObject for Stdio {
-    fn write(&self, buffer: &mut [u8]) -> usize {
+    fn write(&self, buffer: &mut [u8]) -> Transfer {
         let msg = core::str::from_utf8(buffer).expect("Error converting strings");
 
         crate::print!("{}", msg);
 
-        msg.len()
+        Transfer::Done(msg.len(), None)
     }
 }
@@ -0,0 +1,700 @@
+//! Loads ELF executables into a thread's user address space
+//!
+//! Segments are streamed from the file one page at a time, so the size of a program is only
+//! limited by the amount of free pages.
+//!
+//! Position independent executables (ET_DYN) are loaded at a fixed base address and their
+//! relative relocations are applied after the segments have been loaded. There is no dynamic
+//! linker, so programs that need symbol lookups are rejected.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use core::mem;
+
+use crate::{
+    aarch64::cpu,
+    allocator::page_allocator::{Page, PAGE_SIZE},
+    elf::{
+        DynamicEntry, ELF64Header, ELFFileClass, ObjectFileType, ProgramHeader, ProgramType,
+        RelocationEntry,
+    },
+    platform::{
+        kernel_object::FileObject,
+        page_table::{PagePermissions, PageTable},
+        platform_devices::PLATFORM,
+        thread::Thread,
+    },
+};
+
+/// Reasons an exec can fail. The value is returned to the calling thread in x0.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u64)]
+pub enum ExecError {
+    /// The program could not be found
+    FileNotFound = 1,
+    /// The file does not start with a valid ELF header
+    InvalidELF = 2,
+    /// The file is a valid ELF, but not an AArch64 executable
+    UnsupportedELF = 3,
+    /// The program header table is malformed or lies outside of the file
+    InvalidProgramHeader = 4,
+    /// A loadable segment has inconsistent sizes or addresses
+    InvalidSegment = 5,
+    /// The file ended before a segment could be read
+    ReadFailed = 6,
+    /// The arguments and environment do not fit on the initial stack
+    ArgumentsTooLarge = 7,
+    /// The program needs relocations other than relative ones, or a dynamic linker
+    UnsupportedRelocation = 8,
+    /// The dynamic section or the relocation tables are malformed
+    InvalidDynamicSection = 9,
+    /// The pages or the page tables of the program could not be allocated
+    OutOfMemory = 10,
+    /// The path, the argument arrays or their strings lie outside of the caller's memory
+    InvalidAddress = 11,
+}
+
+/// Virtual address of the page that is used as the initial user stack
+pub const USER_STACK_PAGE: u64 = 0x80_000;
+
+/// User programs are mapped through ttbr0, which covers a 48 bit address space
+pub const USER_ADDRESS_LIMIT: u64 = 1 << 48;
+
+/// Base address that position independent executables are loaded at
+pub const PIE_BASE_ADDRESS: u64 = 0x40_0000;
+
+const KERNEL_ADDRESS_OFFSET: u64 = 0xFFFF_0000_0000_0000;
+
+/// Upper bound on the number of strings read from a user argv or envp array
+const MAX_ARGUMENTS: usize = 256;
+
+/// Auxiliary vector entry types from the System V ABI
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_ENTRY: u64 = 9;
+
+/// Register state that a freshly loaded program starts with.
+/// Besides the System V stack layout, argc, argv and envp are passed in x0-x2 for programs without
+/// a C runtime.
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramEntry {
+    pub entry_address: u64,
+    pub stack_pointer: u64,
+    pub argc: u64,
+    pub argv: u64,
+    pub envp: u64,
+}
+
+/// The arguments and environment strings passed to a program
+#[derive(Debug, Clone)]
+pub struct ProgramArguments {
+    arguments: Vec<Vec<u8>>,
+    environment: Vec<Vec<u8>>,
+}
+
+impl ProgramArguments {
+    pub fn new(arguments: &[&str], environment: &[&str]) -> Self {
+        Self {
+            arguments: arguments
+                .iter()
+                .map(|arg| arg.as_bytes().to_vec())
+                .collect(),
+            environment: environment
+                .iter()
+                .map(|var| var.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    /// Copies null terminated arrays of C strings out of the calling program, so that they
+    /// survive the old image being torn down. Null arrays are treated as empty. Pointers of a
+    /// thread that called from EL0 are checked against its memory, those of kernel threads are
+    /// trusted.
+    pub unsafe fn from_user(
+        thread: &Thread,
+        arguments: *const *const u8,
+        environment: *const *const u8,
+    ) -> Result<Self, ExecError> {
+        Ok(Self {
+            arguments: Self::copy_strings(thread, arguments)?,
+            environment: Self::copy_strings(thread, environment)?,
+        })
+    }
+
+    unsafe fn copy_strings(
+        thread: &Thread,
+        array: *const *const u8,
+    ) -> Result<Vec<Vec<u8>>, ExecError> {
+        let mut strings = Vec::new();
+
+        if array.is_null() {
+            return Ok(strings);
+        }
+
+        if !array.is_aligned() {
+            return Err(ExecError::InvalidAddress);
+        }
+
+        for i in 0.. {
+            let slot = array.wrapping_add(i);
+
+            if !thread.can_access(slot as u64, mem::size_of::<*const u8>() as u64, false) {
+                return Err(ExecError::InvalidAddress);
+            }
+
+            let string = *slot;
+
+            if string.is_null() {
+                break;
+            }
+
+            if i == MAX_ARGUMENTS {
+                return Err(ExecError::ArgumentsTooLarge);
+            }
+
+            strings.push(Self::copy_string(thread, string)?);
+        }
+
+        Ok(strings)
+    }
+
+    /// Copies the bytes of a C string up to the terminating null, checking each page it reaches
+    unsafe fn copy_string(thread: &Thread, string: *const u8) -> Result<Vec<u8>, ExecError> {
+        let mut bytes = Vec::new();
+
+        loop {
+            let address = string.wrapping_add(bytes.len());
+
+            if (bytes.is_empty() || (address as usize).is_multiple_of(PAGE_SIZE))
+                && !thread.can_access(address as u64, 1, false)
+            {
+                return Err(ExecError::InvalidAddress);
+            }
+
+            match *address {
+                0 => return Ok(bytes),
+                // Longer strings could not fit on the initial stack page anyway
+                _ if bytes.len() == PAGE_SIZE => return Err(ExecError::ArgumentsTooLarge),
+                byte => bytes.push(byte),
+            }
+        }
+    }
+
+    /// Bytes needed on the stack for the strings, the pointer arrays and an auxiliary vector
+    /// with the given number of entries, including alignment padding
+    fn stack_size(&self, auxiliary_entries: usize) -> usize {
+        let strings: usize = self
+            .arguments
+            .iter()
+            .chain(self.environment.iter())
+            .map(|string| string.len() + 1)
+            .sum();
+
+        let words = 1 + (self.arguments.len() + 1) + (self.environment.len() + 1);
+
+        strings + 8 * words + 16 * (auxiliary_entries + 1) + 2 * 16
+    }
+
+    /// Lays out the initial stack in the System V AArch64 convention at the top of the page, which
+    /// is mapped at page_address: argc, the argv pointers, a null, the envp pointers, a null and
+    /// the auxiliary vector, followed by the strings themselves.
+    fn write_to_stack(
+        &self,
+        page: &mut Page,
+        page_address: u64,
+        auxiliary_vector: &[(u64, u64)],
+        entry_address: u64,
+    ) -> ProgramEntry {
+        let mut string_offset = PAGE_SIZE;
+        let mut argument_pointers = Vec::with_capacity(self.arguments.len());
+        let mut environment_pointers = Vec::with_capacity(self.environment.len());
+
+        for (strings, pointers) in [
+            (&self.arguments, &mut argument_pointers),
+            (&self.environment, &mut environment_pointers),
+        ] {
+            for string in strings {
+                string_offset -= string.len() + 1;
+
+                page[string_offset..string_offset + string.len()].copy_from_slice(string);
+                page[string_offset + string.len()] = 0;
+
+                pointers.push(page_address + string_offset as u64);
+            }
+        }
+
+        let mut words = Vec::new();
+
+        words.push(self.arguments.len() as u64);
+        words.extend_from_slice(&argument_pointers);
+        words.push(0);
+        words.extend_from_slice(&environment_pointers);
+        words.push(0);
+
+        for (key, value) in auxiliary_vector {
+            words.push(*key);
+            words.push(*value);
+        }
+
+        words.push(AT_NULL);
+        words.push(0);
+
+        // The stack pointer has to be 16 byte aligned
+        let stack_offset = (string_offset - 8 * words.len()) & !0xF;
+
+        for (i, word) in words.iter().enumerate() {
+            let offset = stack_offset + 8 * i;
+
+            page[offset..offset + 8].copy_from_slice(&word.to_le_bytes());
+        }
+
+        let stack_pointer = page_address + stack_offset as u64;
+
+        ProgramEntry {
+            entry_address,
+            stack_pointer,
+            argc: self.arguments.len() as u64,
+            argv: stack_pointer + 8,
+            envp: stack_pointer + 8 * (self.arguments.len() as u64 + 2),
+        }
+    }
+}
+
+pub struct ElfLoader<'a> {
+    file: &'a FileObject,
+    header: ELF64Header,
+    program_headers: Vec<ProgramHeader>,
+    /// Added to every address in the file, zero for ET_EXEC files
+    base_address: u64,
+    relocations: Vec<RelocationEntry>,
+}
+
+impl<'a> ElfLoader<'a> {
+    /// Reads and validates the ELF header and the program headers. No address space is modified,
+    /// so a failed exec can still return to the calling program.
+    pub fn new(file: &'a FileObject) -> Result<Self, ExecError> {
+        let mut header_buffer = [0; core::mem::size_of::<ELF64Header>()];
+
+        if file.read_at(0, &mut header_buffer) != header_buffer.len() {
+            return Err(ExecError::InvalidELF);
+        }
+
+        let header =
+            ELF64Header::try_from(&header_buffer[..]).map_err(|_| ExecError::InvalidELF)?;
+
+        if header.elf_identification.file_class != ELFFileClass::Class64
+            || header.e_machine != ELF64Header::MACHINE_AARCH64
+        {
+            return Err(ExecError::UnsupportedELF);
+        }
+
+        let base_address = match header.object_file_type {
+            ObjectFileType::ExecutableFile => 0,
+            ObjectFileType::SharedObjectFile => PIE_BASE_ADDRESS,
+            _ => return Err(ExecError::UnsupportedELF),
+        };
+
+        let entry_size = header.program_header_entry_size as usize;
+        let number_of_headers = header.program_header_number as usize;
+
+        if entry_size < ProgramHeader::SIZE || number_of_headers == 0 {
+            return Err(ExecError::InvalidProgramHeader);
+        }
+
+        let table_start = header.program_header_offset as usize;
+        let table_size = entry_size * number_of_headers;
+
+        match table_start.checked_add(table_size) {
+            Some(table_end) if table_end <= file.size() => {}
+            _ => return Err(ExecError::InvalidProgramHeader),
+        }
+
+        let program_headers = Self::read_table(file, table_start, table_size, entry_size)?
+            .into_iter()
+            .collect::<Result<Vec<ProgramHeader>, _>>()
+            .map_err(|_| ExecError::InvalidProgramHeader)?;
+
+        if program_headers
+            .iter()
+            .any(|header| header.program_type == ProgramType::Interpreter)
+        {
+            return Err(ExecError::UnsupportedRelocation);
+        }
+
+        let mut loader = Self {
+            file,
+            header,
+            program_headers,
+            base_address,
+            relocations: vec![],
+        };
+
+        for segment in loader.loadable_segments() {
+            loader.validate_segment(segment)?;
+        }
+
+        loader.relocations = loader.read_relocations()?;
+
+        Ok(loader)
+    }
+
+    /// Reads a table of fixed size entries from the file and parses every entry
+    fn read_table<T>(
+        file: &FileObject,
+        offset: usize,
+        size: usize,
+        entry_size: usize,
+    ) -> Result<Vec<Result<T, &'static str>>, ExecError>
+    where
+        T: for<'b> TryFrom<&'b [u8], Error = &'static str>,
+    {
+        let mut table = vec![0; size];
+
+        if file.read_at(offset, &mut table) != size {
+            return Err(ExecError::ReadFailed);
+        }
+
+        Ok(table.chunks_exact(entry_size).map(T::try_from).collect())
+    }
+
+    pub fn entry_address(&self) -> u64 {
+        self.base_address + self.header.program_entry_address
+    }
+
+    /// The address behind the highest loadable segment, where the heap of the program starts
+    pub fn image_end(&self) -> u64 {
+        self.loadable_segments()
+            .map(|segment| self.segment_address(segment) + segment.memory_size)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The address a segment is loaded at
+    fn segment_address(&self, segment: &ProgramHeader) -> u64 {
+        self.base_address + segment.virtual_address
+    }
+
+    /// Checks that the arguments fit on the initial stack
+    pub fn validate_arguments(&self, arguments: &ProgramArguments) -> Result<(), ExecError> {
+        if arguments.stack_size(self.auxiliary_vector().len()) > PAGE_SIZE {
+            Err(ExecError::ArgumentsTooLarge)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes the arguments and the auxiliary vector to the stack page
+    pub fn set_up_stack(
+        &self,
+        stack_page: &mut Page,
+        arguments: &ProgramArguments,
+    ) -> ProgramEntry {
+        arguments.write_to_stack(
+            stack_page,
+            USER_STACK_PAGE,
+            &self.auxiliary_vector(),
+            self.entry_address(),
+        )
+    }
+
+    fn auxiliary_vector(&self) -> Vec<(u64, u64)> {
+        let mut auxiliary_vector = vec![];
+
+        if let Some(address) = self.program_header_address() {
+            auxiliary_vector.push((AT_PHDR, address));
+        }
+
+        auxiliary_vector.push((AT_PHENT, self.header.program_header_entry_size as u64));
+        auxiliary_vector.push((AT_PHNUM, self.header.program_header_number as u64));
+        auxiliary_vector.push((AT_PAGESZ, PAGE_SIZE as u64));
+        auxiliary_vector.push((AT_ENTRY, self.entry_address()));
+
+        auxiliary_vector
+    }
+
+    /// The user address of the program header table, if it is part of a loaded segment
+    fn program_header_address(&self) -> Option<u64> {
+        if let Some(header) = self
+            .program_headers
+            .iter()
+            .find(|header| header.program_type == ProgramType::PHeader)
+        {
+            return Some(self.base_address + header.virtual_address);
+        }
+
+        let table_offset = self.header.program_header_offset;
+
+        self.loadable_segments()
+            .find(|segment| {
+                segment.offset <= table_offset && table_offset < segment.offset + segment.file_size
+            })
+            .map(|segment| self.segment_address(segment) + (table_offset - segment.offset))
+    }
+
+    /// Maps every loadable segment into the table and fills it from the file
+    pub fn load(&self, table: &mut PageTable) -> Result<(), ExecError> {
+        for segment in self.loadable_segments() {
+            self.load_segment(table, segment)?;
+        }
+
+        self.apply_relocations(table)
+    }
+
+    /// Collects the relocations from the dynamic section. Only relative relocations with an
+    /// explicit addend are supported, anything else needs a symbol table and is rejected before
+    /// the calling program is torn down.
+    fn read_relocations(&self) -> Result<Vec<RelocationEntry>, ExecError> {
+        let dynamic = match self
+            .program_headers
+            .iter()
+            .find(|header| header.program_type == ProgramType::Dynamic)
+        {
+            Some(dynamic) => dynamic,
+            None => return Ok(vec![]),
+        };
+
+        let dynamic_size =
+            dynamic.file_size as usize - dynamic.file_size as usize % DynamicEntry::SIZE;
+
+        match dynamic.offset.checked_add(dynamic.file_size) {
+            Some(end) if end <= self.file.size() as u64 => {}
+            _ => return Err(ExecError::InvalidDynamicSection),
+        }
+
+        let entries = Self::read_table(
+            self.file,
+            dynamic.offset as usize,
+            dynamic_size,
+            DynamicEntry::SIZE,
+        )?
+        .into_iter()
+        .collect::<Result<Vec<DynamicEntry>, _>>()
+        .map_err(|_| ExecError::InvalidDynamicSection)?;
+
+        let mut rela = None;
+        let mut rela_size = 0;
+        let mut rela_entry_size = RelocationEntry::SIZE as u64;
+        let mut plt_relocations = None;
+        let mut plt_relocations_size = 0;
+
+        for entry in entries
+            .iter()
+            .take_while(|entry| entry.tag != DynamicEntry::NULL)
+        {
+            match entry.tag {
+                DynamicEntry::RELA => rela = Some(entry.value),
+                DynamicEntry::RELA_SIZE => rela_size = entry.value,
+                DynamicEntry::RELA_ENTRY_SIZE => rela_entry_size = entry.value,
+                DynamicEntry::PLT_RELOCATIONS => plt_relocations = Some(entry.value),
+                DynamicEntry::PLT_RELOCATION_SIZE => plt_relocations_size = entry.value,
+                DynamicEntry::PLT_RELOCATION_TYPE if entry.value != DynamicEntry::RELA => {
+                    return Err(ExecError::UnsupportedRelocation)
+                }
+                DynamicEntry::REL | DynamicEntry::REL_SIZE | DynamicEntry::RELR => {
+                    return Err(ExecError::UnsupportedRelocation)
+                }
+                _ => {}
+            }
+        }
+
+        if rela_entry_size < RelocationEntry::SIZE as u64 {
+            return Err(ExecError::InvalidDynamicSection);
+        }
+
+        let mut relocations: Vec<RelocationEntry> = vec![];
+
+        for (address, size) in [(rela, rela_size), (plt_relocations, plt_relocations_size)] {
+            let address = match address {
+                Some(address) if size > 0 => address,
+                _ => continue,
+            };
+
+            let offset = self
+                .file_offset(address, size)
+                .ok_or(ExecError::InvalidDynamicSection)?;
+            let size = size - size % rela_entry_size;
+
+            for relocation in
+                Self::read_table(self.file, offset, size as usize, rela_entry_size as usize)?
+            {
+                relocations.push(relocation.map_err(|_| ExecError::InvalidDynamicSection)?);
+            }
+        }
+
+        for relocation in &relocations {
+            match relocation.relocation_type() {
+                RelocationEntry::R_AARCH64_NONE => {}
+                RelocationEntry::R_AARCH64_RELATIVE => {
+                    if relocation.symbol_index() != 0 || !self.is_loaded(relocation.offset, 8) {
+                        return Err(ExecError::InvalidDynamicSection);
+                    }
+                }
+                _ => return Err(ExecError::UnsupportedRelocation),
+            }
+        }
+
+        Ok(relocations)
+    }
+
+    /// Translates a link time address to an offset in the file, if the whole range is backed by
+    /// the file
+    fn file_offset(&self, address: u64, size: u64) -> Option<usize> {
+        let end = address.checked_add(size)?;
+
+        self.loadable_segments()
+            .find(|segment| {
+                segment.virtual_address <= address
+                    && end <= segment.virtual_address + segment.file_size
+            })
+            .map(|segment| (segment.offset + (address - segment.virtual_address)) as usize)
+    }
+
+    /// Whether a link time address range lies inside of a loadable segment
+    fn is_loaded(&self, address: u64, size: u64) -> bool {
+        let end = match address.checked_add(size) {
+            Some(end) => end,
+            None => return false,
+        };
+
+        self.loadable_segments().any(|segment| {
+            segment.virtual_address <= address
+                && end <= segment.virtual_address + segment.memory_size
+        })
+    }
+
+    /// Writes base + addend to every relative relocation. The writes go through the kernel
+    /// mapping of the pages, so relocations in read only segments work as well.
+    fn apply_relocations(&self, table: &mut PageTable) -> Result<(), ExecError> {
+        for relocation in self.relocations.iter().filter(|relocation| {
+            relocation.relocation_type() == RelocationEntry::R_AARCH64_RELATIVE
+        }) {
+            let value = self.base_address.wrapping_add(relocation.addend as u64);
+
+            if !table.write_bytes(self.base_address + relocation.offset, &value.to_le_bytes()) {
+                return Err(ExecError::InvalidDynamicSection);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn loadable_segments(&self) -> impl Iterator<Item = &ProgramHeader> {
+        self.program_headers
+            .iter()
+            .filter(|header| header.program_type == ProgramType::Loadable)
+    }
+
+    fn validate_segment(&self, segment: &ProgramHeader) -> Result<(), ExecError> {
+        if segment.file_size > segment.memory_size {
+            return Err(ExecError::InvalidSegment);
+        }
+
+        match segment.offset.checked_add(segment.file_size) {
+            Some(file_end) if file_end <= self.file.size() as u64 => {}
+            _ => return Err(ExecError::InvalidSegment),
+        }
+
+        let segment_start = self.segment_address(segment);
+
+        let segment_end = match segment.virtual_address.checked_add(segment.memory_size) {
+            Some(end) if end <= USER_ADDRESS_LIMIT - self.base_address => self.base_address + end,
+            _ => return Err(ExecError::InvalidSegment),
+        };
+
+        let stack_end = USER_STACK_PAGE + PAGE_SIZE as u64;
+
+        if segment.memory_size != 0 && segment_start < stack_end && segment_end > USER_STACK_PAGE {
+            return Err(ExecError::InvalidSegment);
+        }
+
+        // Segments must be placed so that the file offset and the address agree modulo the alignment
+        if segment.alignment > 1
+            && (!segment.alignment.is_power_of_two()
+                || segment.virtual_address % segment.alignment
+                    != segment.offset % segment.alignment)
+        {
+            return Err(ExecError::InvalidSegment);
+        }
+
+        Ok(())
+    }
+
+    fn load_segment(
+        &self,
+        table: &mut PageTable,
+        segment: &ProgramHeader,
+    ) -> Result<(), ExecError> {
+        let segment_start = self.segment_address(segment);
+        let segment_end = segment_start + segment.memory_size;
+        let file_end = segment_start + segment.file_size;
+
+        let permissions = PagePermissions::new(segment.is_writable(), segment.is_executable());
+
+        let mut page_address = segment_start & !(PAGE_SIZE as u64 - 1);
+
+        while page_address < segment_end {
+            // Segments may share a page, in which case the earlier mapping is reused and gets the
+            // permissions of both segments
+            let page = match table.translate(page_address) {
+                Some(physical_address) => {
+                    let shared_permissions = table
+                        .get_permissions(page_address)
+                        .map_or(permissions, |existing| existing.union(permissions));
+
+                    if !table.map_user_address(page_address, physical_address, shared_permissions) {
+                        return Err(ExecError::OutOfMemory);
+                    }
+
+                    (physical_address | KERNEL_ADDRESS_OFFSET) as *mut Page
+                }
+                None => {
+                    let page = PLATFORM.allocate_pages(0).ok_or(ExecError::OutOfMemory)?;
+
+                    unsafe {
+                        (*page.page).fill(0);
+                    }
+
+                    if !table.map_user_address(page_address, page.page as u64, permissions) {
+                        PLATFORM.free_page(page.page as usize);
+                        return Err(ExecError::OutOfMemory);
+                    }
+
+                    page.page
+                }
+            };
+
+            let page = unsafe { &mut *page };
+
+            // The part of the segment that lies in this page
+            let start = max(page_address, segment_start);
+            let end = min(page_address + PAGE_SIZE as u64, segment_end);
+
+            // Bytes up to copy_end come from the file, the rest is bss and is zero filled
+            let copy_end = min(max(start, file_end), end);
+
+            if copy_end > start {
+                let destination =
+                    &mut page[(start - page_address) as usize..(copy_end - page_address) as usize];
+                let file_offset = (segment.offset + (start - segment_start)) as usize;
+
+                if self.file.read_at(file_offset, destination) != destination.len() {
+                    return Err(ExecError::ReadFailed);
+                }
+            }
+
+            page[(copy_end - page_address) as usize..(end - page_address) as usize].fill(0);
+
+            if permissions.executable {
+                cpu::synchronize_instruction_cache(page.as_ptr() as usize, PAGE_SIZE);
+            }
+
+            page_address += PAGE_SIZE as u64;
+        }
+
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use core::ptr;
 
-use crate::{platform::{emmc::EMMCRegisters, gpio::GPIORegisters, interrupt::InterruptRegisters, mailbox::MailboxRegisters, mini_uart::MiniUARTRegisters}, sync::SpinMutex};
+use crate::{platform::{emmc::EMMCRegisters, gpio::GPIORegisters, interrupt::{InterruptRegisters, LocalInterruptRegisters}, mailbox::MailboxRegisters, mini_uart::MiniUARTRegisters}, sync::SpinMutex};
 
 use super::{
     timer::TimerRegisters
@@ -15,6 +15,7 @@ unsafe extern "C" {
     unsafe static mut MAILBOX_REGISTERS: MailboxRegisters;
     unsafe static mut EMMC_REGISTERS: EMMCRegisters;
     unsafe static mut MINI_UART_REGISTERS: MiniUARTRegisters;
+    unsafe static mut LOCAL_INTERRUPT_REGISTERS: LocalInterruptRegisters;
 }
 
 //const MMIO_START: usize = 0x3F00_0000;
@@ -68,6 +69,12 @@ pub const fn get_interrupt_registers() -> &'static mut InterruptRegisters {
     }
 }
 
+pub const fn get_local_interrupt_registers() -> &'static mut LocalInterruptRegisters {
+    unsafe {
+        &mut *ptr::addr_of_mut!(LOCAL_INTERRUPT_REGISTERS)
+    }
+}
+
 /*pub struct MMIOController {
     start: usize,
     length: usize,
@@ -1,7 +1,7 @@
 use crate::{
     aarch64::{
         self,
-        mmu::{Address, TableDescriptor, TableEntry},
+        mmu::{self, Address, TableDescriptor, TableEntry},
     },
     allocator::page_allocator::PAGE_SIZE,
     platform::platform_devices::PLATFORM,
@@ -9,6 +9,41 @@ use crate::{
 
 pub type Table = [usize; 512];
 
+/// Access rights of a user page. User pages are never executable by the kernel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PagePermissions {
+    pub writable: bool,
+    pub executable: bool,
+}
+
+impl PagePermissions {
+    pub const READ_ONLY: Self = Self::new(false, false);
+    pub const READ_WRITE: Self = Self::new(true, false);
+    pub const READ_EXECUTE: Self = Self::new(false, true);
+
+    pub const fn new(writable: bool, executable: bool) -> Self {
+        Self {
+            writable,
+            executable,
+        }
+    }
+
+    /// The permissions needed by a page that is shared by two mappings
+    pub fn union(self, other: Self) -> Self {
+        Self::new(
+            self.writable || other.writable,
+            self.executable || other.executable,
+        )
+    }
+
+    fn from_entry(entry: TableEntry) -> Self {
+        Self::new(
+            entry.get_access_permission() == TableEntry::READ_WRITE,
+            entry.get_unprivileged_execute_never() == 0,
+        )
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct PageTable {
     pgd: *mut Table,
@@ -43,8 +78,15 @@ impl PageTable {
         }
     }
 
-    // TODO: how to handle errors/preconditions?
-    pub fn map_user_address(&mut self, virtual_address: u64, physical_address: u64) {
+    /// Maps the page at the physical address to the virtual address, creating the intermediate
+    /// tables it needs. Returns false without mapping the page if a table could not be allocated.
+    #[must_use]
+    pub fn map_user_address(
+        &mut self,
+        virtual_address: u64,
+        physical_address: u64,
+        permissions: PagePermissions,
+    ) -> bool {
         // Assumes 48 bit address space with 4k page.
         let vaddr = Address::new(virtual_address);
         let paddr = Address::new(physical_address);
@@ -57,76 +99,254 @@ impl PageTable {
         let pld_index = vaddr.get_pld() as usize;
         let pte_index = vaddr.get_pte() as usize;
 
-        let pgd_entry = TableDescriptor::new(unsafe { (*self.pgd)[pgd_index] as u64 });
+        let pte = match Self::next_table(self.pgd, pgd_index)
+            .and_then(|pud| Self::next_table(pud, pud_index))
+            .and_then(|pld| Self::next_table(pld, pld_index))
+        {
+            Some(pte) => pte,
+            None => return false,
+        };
 
-        let pud;
+        //let pte_entry = TableEntry::from(unsafe { (*pte)[pte_index] });
 
-        if pgd_entry.is_valid() {
-            pud = (pgd_entry.get_next_table_address() | 0xFFFF_0000_0000_0000) as *mut Table
+        // TODO: should we overwrite previous mappings?
+        let entry = paddr.get_pte_entry();
+        let access_permission = if permissions.writable {
+            TableEntry::READ_WRITE
         } else {
-            let page = PLATFORM.allocate_zeroed_page();
+            TableEntry::READ_ONLY
+        };
+
+        let pte_entry = TableEntry::from(entry & 0xFFFF_FFFF_FFFF)
+            .set_id(0b11)
+            .set_attribute_index(mmu::NORMAL_ATTRIBUTE_INDEX as u64)
+            .set_shareability(mmu::INNER_SHAREABLE)
+            .set_access_permission(access_permission)
+            .set_access_flag(1)
+            .set_privileged_execute_never(1)
+            .set_unprivileged_execute_never(!permissions.executable as u64);
+
+        unsafe {
+            (*pte)[pte_index] = pte_entry.get_value() as usize;
+        }
 
-            let pud_addr = page.page as usize;
-            pud = page.page as *mut Table;
+        true
+    }
+
+    /// The table that the entry at the index points to. A missing table is allocated, unless the
+    /// page allocator has run out of pages.
+    fn next_table(table: *mut Table, index: usize) -> Option<*mut Table> {
+        let entry = TableDescriptor::new(unsafe { (*table)[index] } as u64);
+
+        if entry.is_valid() {
+            return Some((entry.get_next_table_address() | 0xFFFF_0000_0000_0000) as *mut Table);
+        }
+
+        let page = PLATFORM.allocate_pages(0)?;
+
+        unsafe {
+            (*page.page).fill(0);
+        }
+
+        let descriptor =
+            TableDescriptor::new(page.page as u64 & 0xFFFF_FFFF_FFFF).set_identifier(0b11);
 
-            let descriptor =
-                TableDescriptor::new(pud_addr as u64 & 0xFFFF_FFFF_FFFF).set_identifier(0b11);
+        unsafe { (*table)[index] = descriptor.get_value() as usize };
 
-            unsafe {
-                (*self.pgd)[pgd_index] = descriptor.get_value() as usize;
+        Some(page.page as *mut Table)
+    }
+
+    /// Removes every mapping in the table, returning the mapped pages and the intermediate tables to
+    /// the page allocator. The top level table itself is kept so that the table can be reused.
+    pub fn unmap_all(&mut self) {
+        unsafe {
+            for pgd_index in 0..Self::TABLE_LENGTH {
+                let pgd_entry = TableDescriptor::new((*self.pgd)[pgd_index] as u64);
+
+                if pgd_entry.get_identifier() == 0b11 {
+                    Self::free_table(pgd_entry.get_next_table_address(), 1);
+                }
+
+                (*self.pgd)[pgd_index] = 0;
             }
         }
 
-        let pud_entry = TableDescriptor::new(unsafe { (*pud)[pud_index] } as u64);
+        aarch64::mmu::invalidate_tlb();
+    }
 
-        let pld;
+    /// Unmaps everything and frees the top level table. The table must not be used afterwards.
+    pub fn free(&mut self) {
+        self.unmap_all();
 
-        if pud_entry.is_valid() {
-            pld = (pud_entry.get_next_table_address() | 0xFFFF_0000_0000_0000) as *mut Table;
-        } else {
-            let page = PLATFORM.allocate_zeroed_page();
+        PLATFORM.free_page(self.pgd as usize);
+    }
 
-            let pld_addr = page.page as usize;
-            pld = pld_addr as *mut Table;
+    /// Frees a table at the given level (1 for the pud, 3 for the pte) along with everything it maps
+    unsafe fn free_table(table_address: u64, level: usize) {
+        let table = (table_address | 0xFFFF_0000_0000_0000) as *mut Table;
 
-            let descriptor =
-                TableDescriptor::new(pld_addr as u64 & 0xFFFF_FFFF_FFFF).set_identifier(0b11);
+        for index in 0..Self::TABLE_LENGTH {
+            let entry = TableDescriptor::new((*table)[index] as u64);
 
-            unsafe { (*pud)[pud_index] = descriptor.get_value() as usize };
+            // Level 3 entries use the table identifier for pages, earlier levels for tables
+            if entry.get_identifier() == 0b11 {
+                let next_address = entry.get_next_table_address();
+
+                if level == 3 {
+                    PLATFORM.free_page(next_address as usize | 0xFFFF_0000_0000_0000);
+                } else {
+                    Self::free_table(next_address, level + 1);
+                }
+            }
         }
 
-        let pld_entry = TableDescriptor::new(unsafe { (*pld)[pld_index] as u64 });
+        PLATFORM.free_page(table as usize);
+    }
 
-        let pte;
-        if pld_entry.is_valid() {
-            pte = (pld_entry.get_next_table_address() | 0xFFFF_0000_0000_0000) as *mut Table;
-        } else {
-            let page = PLATFORM.allocate_zeroed_page();
+    pub fn is_addr_mapped(&self, addr: u64) -> bool {
+        self.translate(addr).is_some()
+    }
+
+    /// Returns the physical address that a virtual address is mapped to, if it is mapped
+    pub fn translate(&self, addr: u64) -> Option<u64> {
+        let offset = addr & 0xFFF;
 
-            let pte_addr = page.page as usize;
-            pte = pte_addr as *mut Table;
+        self.get_page_entry(addr)
+            .map(|entry| entry.get_next_table_address() | offset)
+    }
 
-            let descriptor =
-                TableDescriptor::new(pte_addr as u64 & 0xFFFF_FFFF_FFFF).set_identifier(0b11);
+    /// Copies user memory into the buffer through the kernel mapping of the physical pages.
+    /// Returns false if any part of the range is not mapped.
+    pub fn read_bytes(&self, addr: u64, buffer: &mut [u8]) -> bool {
+        let mut copied = 0;
+
+        while copied < buffer.len() {
+            let address = addr + copied as u64;
+            let length = core::cmp::min(
+                PAGE_SIZE - (address as usize & 0xFFF),
+                buffer.len() - copied,
+            );
+
+            match self.translate(address) {
+                Some(physical_address) => unsafe {
+                    let source = (physical_address | 0xFFFF_0000_0000_0000) as *const u8;
+                    core::ptr::copy_nonoverlapping(source, buffer[copied..].as_mut_ptr(), length);
+                },
+                None => return false,
+            }
 
-            unsafe { (*pld)[pld_index] = descriptor.get_value() as usize };
+            copied += length;
         }
 
-        //let pte_entry = TableEntry::from(unsafe { (*pte)[pte_index] });
+        true
+    }
 
-        // TODO: should we overwrite previous mappings?
-        let entry = paddr.get_pte_entry();
-        let pte_entry = TableEntry::from(entry & 0xFFFF_FFFF_FFFF)
-            .set_id(0b11)
-            .set_access_permission(0b01)
-            .set_access_flag(1);
+    /// Copies the data into user memory through the kernel mapping of the physical pages, so
+    /// read only pages can be written as well. Returns false if any part of the range is not mapped.
+    pub fn write_bytes(&mut self, addr: u64, data: &[u8]) -> bool {
+        let mut copied = 0;
+
+        while copied < data.len() {
+            let address = addr + copied as u64;
+            let length =
+                core::cmp::min(PAGE_SIZE - (address as usize & 0xFFF), data.len() - copied);
+
+            match self.translate(address) {
+                Some(physical_address) => unsafe {
+                    let destination = (physical_address | 0xFFFF_0000_0000_0000) as *mut u8;
+                    core::ptr::copy_nonoverlapping(data[copied..].as_ptr(), destination, length);
+                },
+                None => return false,
+            }
+
+            copied += length;
+        }
+
+        true
+    }
+
+    /// Returns the permissions of the page containing the address, if it is mapped
+    pub fn get_permissions(&self, addr: u64) -> Option<PagePermissions> {
+        self.get_page_entry(addr)
+            .map(|entry| PagePermissions::from_entry(TableEntry::from(entry.get_value())))
+    }
+
+    /// Returns the lowest mapped page in the range. Ranges without intermediate tables are skipped
+    /// as a whole, so that large ranges of a sparse table are searched quickly.
+    pub fn first_mapped_page(&self, start: u64, end: u64) -> Option<u64> {
+        let mut address = start & !0xFFF;
+
+        while address < end {
+            let span = match self.walk(address) {
+                Ok(slot) if TableDescriptor::new(unsafe { *slot } as u64).is_valid() => {
+                    return Some(address)
+                }
+                Ok(_) => PAGE_SIZE as u64,
+                Err(span) => span,
+            };
+
+            address = (address & !(span - 1)) + span;
+        }
+
+        None
+    }
+
+    /// Whether every page overlapping the range is mapped, and writable if the range is written
+    pub fn is_range_accessible(&self, addr: u64, length: u64, write: bool) -> bool {
+        if length == 0 {
+            return true;
+        }
+
+        let last = match addr.checked_add(length - 1) {
+            Some(last) => last,
+            None => return false,
+        };
+
+        (addr & !0xFFF..=last & !0xFFF)
+            .step_by(PAGE_SIZE)
+            .all(|page| {
+                self.get_permissions(page)
+                    .is_some_and(|permissions| permissions.writable || !write)
+            })
+    }
+
+    /// Removes the mapping of the page containing the address and returns the physical address of
+    /// the page, which the caller frees once the TLB has been invalidated. Intermediate tables are
+    /// kept until the table is unmapped as a whole.
+    pub fn unmap_user_address(&mut self, virtual_address: u64) -> Option<u64> {
+        let slot = self.page_entry_slot(virtual_address)?;
+        let entry = TableDescriptor::new(unsafe { *slot } as u64);
+
+        if !entry.is_valid() {
+            return None;
+        }
 
         unsafe {
-            (*pte)[pte_index] = pte_entry.get_value() as usize;
+            *slot = 0;
         }
+
+        Some(entry.get_next_table_address())
     }
 
-    pub fn is_addr_mapped(&self, addr: u64) -> bool {
+    fn get_page_entry(&self, addr: u64) -> Option<TableDescriptor> {
+        let slot = self.page_entry_slot(addr)?;
+        let pte_entry = TableDescriptor::new(unsafe { *slot } as u64);
+
+        if !pte_entry.is_valid() {
+            None
+        } else {
+            Some(pte_entry)
+        }
+    }
+
+    /// Returns the level 3 entry of the address, if the tables leading to it exist
+    fn page_entry_slot(&self, addr: u64) -> Option<*mut usize> {
+        self.walk(addr).ok()
+    }
+
+    /// Finds the slot of the level 3 entry for the address. If an intermediate table is missing,
+    /// returns the size of the address range that the missing entry would cover instead.
+    fn walk(&self, addr: u64) -> Result<*mut usize, u64> {
         let addr = Address::new(addr);
 
         let pgd_index = addr.get_pgd() as usize;
@@ -137,7 +357,7 @@ impl PageTable {
         let pgd_entry = TableDescriptor::new(unsafe { (*self.pgd)[pgd_index] as u64 });
 
         if !pgd_entry.is_valid() {
-            return false;
+            return Err(1 << 39);
         }
 
         let pud = (pgd_entry.get_next_table_address() | 0xFFFF_0000_0000_0000) as *mut Table;
@@ -145,7 +365,7 @@ impl PageTable {
         let pud_entry = TableDescriptor::new(unsafe { (*pud)[pud_index] } as u64);
 
         if !pud_entry.is_valid() {
-            return false;
+            return Err(1 << 30);
         }
 
         let pld = (pud_entry.get_next_table_address() | 0xFFFF_0000_0000_0000) as *mut Table;
@@ -153,17 +373,11 @@ impl PageTable {
         let pld_entry = TableDescriptor::new(unsafe { (*pld)[pld_index] } as u64);
 
         if !pld_entry.is_valid() {
-            return false;
+            return Err(1 << 21);
         }
 
         let pte = (pld_entry.get_next_table_address() | 0xFFFF_0000_0000_0000) as *mut Table;
 
-        let pte_entry = TableDescriptor::new(unsafe { (*pte)[pte_index] } as u64);
-
-        if !pte_entry.is_valid() {
-            return false;
-        } else {
-            return true;
-        }
+        Ok(unsafe { &mut (*pte)[pte_index] as *mut usize })
     }
 }
@@ -0,0 +1,179 @@
+//! Anonymous pipes, byte streams from a write end to a read end
+//!
+//! Reads wait while the pipe is empty and writes wait while it is full. Once the write end has
+//! been closed, reads of an empty pipe return 0 to signal the end of the stream, and once the read
+//! end has been closed writes return 0.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+
+use super::kernel_object::{KernelObject, Transfer};
+use super::wait_queue::WaitQueueID;
+use crate::aarch64::interrupt::IRQLock;
+
+/// Number of bytes a pipe buffers
+pub const PIPE_CAPACITY: usize = 4096;
+
+/// Returned by the pipe syscall if the handles cannot be stored at the given address
+pub const PIPE_ERROR: u64 = u64::MAX;
+
+#[derive(Debug)]
+struct RingBuffer {
+    data: Box<[u8]>,
+    /// Index of the oldest byte
+    start: usize,
+    length: usize,
+}
+
+impl RingBuffer {
+    fn new() -> Self {
+        Self {
+            // Allocated on the heap directly, the array would not fit on a kernel stack
+            data: vec![0; PIPE_CAPACITY].into_boxed_slice(),
+            start: 0,
+            length: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.length == PIPE_CAPACITY
+    }
+
+    /// Copies as many bytes as fit into the free space and returns the number copied
+    fn push(&mut self, bytes: &[u8]) -> usize {
+        let count = bytes.len().min(PIPE_CAPACITY - self.length);
+
+        for (i, &byte) in bytes[..count].iter().enumerate() {
+            self.data[(self.start + self.length + i) % PIPE_CAPACITY] = byte;
+        }
+
+        self.length += count;
+
+        count
+    }
+
+    /// Takes as many bytes as are buffered and fit into the buffer, returns the number taken
+    fn pop(&mut self, buffer: &mut [u8]) -> usize {
+        let count = buffer.len().min(self.length);
+
+        for (i, byte) in buffer[..count].iter_mut().enumerate() {
+            *byte = self.data[(self.start + i) % PIPE_CAPACITY];
+        }
+
+        self.start = (self.start + count) % PIPE_CAPACITY;
+        self.length -= count;
+
+        count
+    }
+}
+
+#[derive(Debug)]
+struct PipeState {
+    buffer: RingBuffer,
+    read_end_open: bool,
+    write_end_open: bool,
+}
+
+#[derive(Debug)]
+pub struct Pipe {
+    state: IRQLock<PipeState>,
+}
+
+impl Pipe {
+    /// Creates a pipe and returns its read end and write end
+    pub fn create() -> (PipeReader, PipeWriter) {
+        let pipe = Arc::new(Self {
+            state: IRQLock::new(PipeState {
+                buffer: RingBuffer::new(),
+                read_end_open: true,
+                write_end_open: true,
+            }),
+        });
+
+        (
+            PipeReader {
+                pipe: Arc::clone(&pipe),
+            },
+            PipeWriter { pipe },
+        )
+    }
+
+    /// Threads waiting for bytes to read
+    fn readers_queue(self: &Arc<Self>) -> WaitQueueID {
+        WaitQueueID::ObjectReaders(Arc::as_ptr(self) as usize)
+    }
+
+    /// Threads waiting for space to write to
+    fn writers_queue(self: &Arc<Self>) -> WaitQueueID {
+        WaitQueueID::ObjectWriters(Arc::as_ptr(self) as usize)
+    }
+}
+
+#[derive(Debug)]
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+}
+
+#[derive(Debug)]
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+}
+
+impl KernelObject for PipeReader {
+    fn read(&self, buffer: &mut [u8]) -> Transfer {
+        let mut state = self.pipe.state.lock();
+
+        if buffer.is_empty() || (state.buffer.is_empty() && !state.write_end_open) {
+            Transfer::Done(0, None)
+        } else if state.buffer.is_empty() {
+            Transfer::Blocked(self.pipe.readers_queue())
+        } else {
+            let count = state.buffer.pop(buffer);
+
+            Transfer::Done(count, Some(self.pipe.writers_queue()))
+        }
+    }
+
+    /// Writers waiting for space will never get it
+    fn close(&self) -> Option<WaitQueueID> {
+        Some(self.pipe.writers_queue())
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.pipe.state.lock().read_end_open = false;
+    }
+}
+
+impl KernelObject for PipeWriter {
+    fn write(&self, buffer: &mut [u8]) -> Transfer {
+        let mut state = self.pipe.state.lock();
+
+        if buffer.is_empty() || !state.read_end_open {
+            Transfer::Done(0, None)
+        } else if state.buffer.is_full() {
+            Transfer::Blocked(self.pipe.writers_queue())
+        } else {
+            let count = state.buffer.push(buffer);
+
+            Transfer::Done(count, Some(self.pipe.readers_queue()))
+        }
+    }
+
+    /// Readers waiting for bytes see the end of the stream instead
+    fn close(&self) -> Option<WaitQueueID> {
+        Some(self.pipe.readers_queue())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.pipe.state.lock().write_end_open = false;
+    }
+}
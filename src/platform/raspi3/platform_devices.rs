@@ -1,6 +1,12 @@
 use crate::{
-    aarch64::{interrupt::IRQLock, syscall::SyscallArgs},
-    allocator::page_allocator::{Page, PageAllocator, PageRef, PAGE_SIZE},
+    aarch64::{
+        backtrace::{self, KernelSymbols},
+        cpu, generic_timer,
+        interrupt::{IRQLock, IRQLockGuard},
+        registers::ExceptionSyndromeRegister,
+        syscall::SyscallArgs,
+    },
+    allocator::page_allocator::{Page, PageRef, PAGE_SIZE},
     device::sector_device::{Sector, SectorDevice},
     filesystem::fat32::{FAT32DirectoryEntry, FAT32Filesystem},
     platform::{
@@ -8,8 +14,9 @@ use crate::{
         emmc::{self, EMMCConfiguration, EMMCController, EMMCRegisters},
         gpio::{GPIOController, GPIORegisters, StatusLight},
         hardware_config::HardwareConfig,
-        interrupt::{InterruptRegisters, InterruptType},
-        kernel::{self, Kernel, TICK},
+        interrupt::{InterruptRegisters, InterruptType, LocalInterruptRegisters},
+        kernel::{self, Kernel, MIN_WAKE_UP_DELAY, TICK},
+        loader::ProgramArguments,
         mailbox::{MailboxBuffer, MailboxController, MailboxRegisters},
         raspi3::exception::InterruptFrame,
         thread::Thread,
@@ -69,6 +76,7 @@ pub struct Platform<'a> {
     devices: Devices<'a>,
     interrupt_handlers: InterruptHandler,
     kernel: IRQLock<Option<Kernel<'a>>>,
+    symbols: IRQLock<Option<KernelSymbols>>,
 }
 
 impl<'a> Platform<'a> {
@@ -77,6 +85,7 @@ impl<'a> Platform<'a> {
             devices: Devices::uninitialized(),
             interrupt_handlers: InterruptHandler::new(),
             kernel: IRQLock::new(None),
+            symbols: IRQLock::new(None),
         }
     }
 
@@ -108,32 +117,89 @@ impl<'a> Platform<'a> {
         HardwareConfig::from_mailbox(self.get_mailbox_controller())
     }
 
-    pub fn set_kernel_timeout(&self, millis: u32) {
+    /// Raises the kernel timer interrupt on core 0 at the given time in microseconds, to wake up
+    /// sleeping threads. Times that have already passed fire as soon as possible.
+    pub fn set_wake_up_timer(&self, time: u64) {
         let mut timer_regs = self.devices.timer.lock();
+        let earliest = timer_regs.time() + MIN_WAKE_UP_DELAY;
+
+        timer_regs.set_kernel_wake_up(time.max(earliest));
+    }
 
-        timer_regs.set_kernel_timeout(millis);
+    /// Starts a time slice on the calling core, or stops its timer when it goes idle
+    pub fn set_slice_timer(&self, running: bool) {
+        if running {
+            generic_timer::set_timeout(TICK as u64);
+        } else {
+            generic_timer::disable();
+        }
+    }
+
+    /// Interrupts a core so that it schedules a thread that was queued for it while it was idle
+    pub fn wake_core(&self, core: usize) {
+        self.devices.local_interrupts.lock().send_wake_up(core);
     }
 
     pub fn get_current_thread(&self) -> Option<Arc<Thread<'a>>> {
         self.kernel
             .lock()
             .as_ref()
-            .map(|kernel| Arc::clone(&kernel.scheduler.current_thread))
+            .map(|kernel| Arc::clone(kernel.scheduler.current_thread()))
+    }
+
+    /// Adds the calling core to the scheduler, whatever it is running becomes its idle thread
+    pub fn register_core(&self) {
+        if let Some(ref mut kernel) = *self.kernel.lock() {
+            kernel.add_core();
+        }
+    }
+
+    /// Enables the interrupts of the calling core's time slice timer and of wake ups from other
+    /// cores
+    pub fn enable_core_interrupts(&self) {
+        let core = cpu::core_id();
+        let mut local_interrupts = self.devices.local_interrupts.lock();
+
+        local_interrupts.enable_core_timer_interrupt(core);
+        local_interrupts.enable_mailbox_interrupt(core);
     }
 
     pub fn handle_interrupt(&self) {
+        let core = cpu::core_id();
+        let source = self
+            .devices
+            .local_interrupts
+            .lock()
+            .get_interrupt_source(core);
+
+        // The end of a time slice, or another core queued a thread for this one. The timer is
+        // programmed again on the way out.
+        if source.get_physical_timer() == 1 || source.get_mailbox_0() == 1 {
+            self.devices.local_interrupts.lock().clear_wake_up(core);
+
+            if let Some(ref mut kernel) = *self.kernel.lock() {
+                kernel.tick();
+            }
+
+            self.return_from_exception();
+        }
+
+        // Only core 0 receives the interrupts of the legacy controller
+        if source.get_gpu() == 0 {
+            return;
+        }
+
         let interrupt_type = self.devices.interrupts.borrow().get_interrupt_type();
         if let Some(InterruptType::KernelTimerInterrupt) = interrupt_type {
             //println!("interrupt type {:?}", interrupt_type);
+            // A sleeping thread is due
             if let Some(ref mut kernel) = *self.kernel.lock() {
                 // TODO: are the clears necessary?
                 kernel.tick();
                 self.get_timer().clear_matches();
-
-                self.set_kernel_timeout(TICK);
-
-                kernel.return_from_exception();
             }
+
+            self.return_from_exception();
         }
 
         // Note: we could also just wake thread as part of the tick?
@@ -143,9 +209,9 @@ impl<'a> Platform<'a> {
         }
     }
 
-    pub fn exec(&self, program: &str) {
+    pub fn exec(&self, program: &str, arguments: &[&str], environment: &[&str]) {
         if let Some(ref mut kernel) = *self.kernel.lock() {
-            kernel.exec(program);
+            kernel.exec(program, &ProgramArguments::new(arguments, environment));
         }
     }
 
@@ -169,11 +235,76 @@ impl<'a> Platform<'a> {
         }
     }
 
+    /// Allocates 2^order contiguous pages, aligned to their size, for buffers that devices access
+    /// directly
+    pub fn allocate_pages(&self, order: usize) -> Option<PageRef> {
+        if let Some(ref mut kernel) = *self.kernel.lock() {
+            kernel.allocate_pages(order)
+        } else {
+            panic!();
+        }
+    }
+
+    /// Returns the block of pages starting at the given address to the page allocator. Addresses
+    /// outside of the page section are ignored.
+    pub fn free_page(&self, address: usize) {
+        if let Some(ref mut kernel) = *self.kernel.lock() {
+            kernel.free_page(address);
+        }
+    }
+
     pub fn handle_syscall(&self, syscall_number: usize, args: SyscallArgs) {
         if let Some(ref mut kernel) = *self.kernel.lock() {
             kernel.handle_syscall(syscall_number, args);
-            kernel.return_from_exception();
         }
+
+        self.return_from_exception();
+    }
+
+    /// Kills the current thread after it caused an exception at EL0 and switches to the next one
+    pub fn handle_user_fault(
+        &self,
+        syndrome: ExceptionSyndromeRegister::RegisterBuffer,
+        fault_address: usize,
+    ) {
+        if let Some(ref mut kernel) = *self.kernel.lock() {
+            kernel.kill_current_thread(syndrome, fault_address);
+        }
+
+        self.return_from_exception();
+    }
+
+    /// Returns from the exception into the current thread of the calling core, which may have
+    /// changed while handling it. Does nothing before the kernel is registered.
+    fn return_from_exception(&self) {
+        let mut kernel = self.kernel.lock();
+
+        let thread = match *kernel {
+            Some(ref mut kernel) => {
+                kernel.scheduler.account_time(false);
+                kernel.update_timers();
+
+                Arc::as_ptr(kernel.scheduler.current_thread())
+            }
+            None => return,
+        };
+
+        // The guard would never be dropped, so the lock is released by the thread switch once the
+        // core has left the old stack
+        let kernel_lock = IRQLockGuard::leak(kernel);
+
+        // Only the calling core replaces its current thread, which keeps the thread alive
+        unsafe { (*thread).return_to(kernel_lock) }
+    }
+
+    pub fn register_symbols(&self, symbols: KernelSymbols) {
+        *self.symbols.lock() = Some(symbols);
+    }
+
+    /// Prints a backtrace of the kernel stack, resolved against the kernel symbols if they
+    /// were loaded
+    pub fn print_backtrace(&self, program_counter: Option<u64>, frame_pointer: u64) {
+        backtrace::print_backtrace(program_counter, frame_pointer, self.symbols.lock().as_ref());
     }
 
     pub fn register_kernel(&self, kernel: Kernel<'a>) {
@@ -193,6 +324,14 @@ impl<'a> Platform<'a> {
             0
         }
     }
+
+    pub fn read_at(&self, entry: FAT32DirectoryEntry, offset: usize, buffer: &mut [u8]) -> usize {
+        if let Some(ref mut kernel) = *self.kernel.lock() {
+            kernel.read_at(entry, offset, buffer)
+        } else {
+            0
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -204,6 +343,7 @@ pub struct Devices<'a> {
     emmc: RefCell<&'a mut EMMCRegisters>,
     emmc_configuration: RefCell<EMMCConfiguration>,
     interrupts: RefCell<&'a mut InterruptRegisters>,
+    local_interrupts: IRQLock<&'a mut LocalInterruptRegisters>,
 }
 
 impl<'a> Devices<'a> {
@@ -220,6 +360,7 @@ impl<'a> Devices<'a> {
             emmc: RefCell::new(mmio::get_emmc_registers()),
             emmc_configuration: RefCell::new(EMMCConfiguration::new()),
             interrupts: RefCell::new(mmio::get_interrupt_registers()),
+            local_interrupts: IRQLock::new(mmio::get_local_interrupt_registers()),
         }
     }
 
@@ -293,9 +434,19 @@ impl MailboxController for Devices<'_> {
         buffer: &MailboxBuffer,
         channel: super::mailbox::Channel,
     ) -> u32 {
-        self.mailbox
+        let length = buffer.len() * core::mem::size_of::<u32>();
+
+        // The GPU reads and writes the buffer in memory, past the caches of the core
+        cpu::clean_and_invalidate_data_cache(buffer.as_ptr() as usize, length);
+
+        let response = self
+            .mailbox
             .borrow_mut()
-            .send_message(buffer.as_ptr() as u32, channel)
+            .send_message(buffer.as_ptr() as u32, channel);
+
+        cpu::clean_and_invalidate_data_cache(buffer.as_ptr() as usize, length);
+
+        response
     }
 }
 
@@ -1,4 +1,8 @@
 pub mod counter;
 pub mod ls;
+pub mod memory_test;
+pub mod pipeline;
 pub mod readelf;
+pub mod sync_test;
+pub mod top;
 pub mod write;
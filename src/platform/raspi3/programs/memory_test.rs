@@ -0,0 +1,79 @@
+use crate::aarch64::cpu;
+use crate::allocator::page_allocator::PAGE_SIZE;
+use crate::platform::user_memory::{MEMORY_ERROR, MMAP_START, PROT_WRITE};
+use crate::println;
+
+const PAGES: u64 = 4;
+
+/// Maps pages, checks that they are zeroed and writable and unmaps them again, then checks that
+/// invalid ranges are refused. Exits with the number of failed checks.
+pub extern "C" fn memory_test(_: usize) {
+    let length = PAGES * PAGE_SIZE as u64;
+    let mut failures = 0;
+
+    let mut check = |name: &str, passed: bool| {
+        if !passed {
+            println!("Memory test failed: {}", name);
+            failures += 1;
+        }
+    };
+
+    let address = cpu::mmap(0, length, PROT_WRITE);
+
+    check(
+        "kernel picked address",
+        address >= MMAP_START && address != MEMORY_ERROR,
+    );
+
+    if address != MEMORY_ERROR {
+        let memory =
+            unsafe { core::slice::from_raw_parts_mut(address as *mut u64, length as usize / 8) };
+
+        check("zeroed", memory.iter().all(|&word| word == 0));
+
+        for (i, word) in memory.iter_mut().enumerate() {
+            *word = i as u64;
+        }
+
+        check(
+            "written",
+            memory.iter().enumerate().all(|(i, &word)| word == i as u64),
+        );
+        check(
+            "overlap",
+            cpu::mmap(address, PAGE_SIZE as u64, PROT_WRITE) == MEMORY_ERROR,
+        );
+        check("unmap", cpu::munmap(address, length) == 0);
+        check("remap", cpu::mmap(address, length, PROT_WRITE) == address);
+        check("unmap again", cpu::munmap(address, length) == 0);
+    }
+
+    check("zero length", cpu::mmap(0, 0, PROT_WRITE) == MEMORY_ERROR);
+    check(
+        "too large",
+        cpu::mmap(0, 1 << 47, PROT_WRITE) == MEMORY_ERROR,
+    );
+    check(
+        "unaligned",
+        cpu::mmap(MMAP_START + 1, length, PROT_WRITE) == MEMORY_ERROR,
+    );
+    check(
+        "below mappings",
+        cpu::mmap(PAGE_SIZE as u64, length, PROT_WRITE) == MEMORY_ERROR,
+    );
+    check(
+        "kernel range",
+        cpu::mmap(!0xFFF, length, PROT_WRITE) == MEMORY_ERROR,
+    );
+    check("unmap outside", cpu::munmap(0, length) == MEMORY_ERROR);
+
+    // Kernel threads have no program image and so no heap
+    check(
+        "no heap",
+        cpu::brk(0) == 0 && cpu::sbrk(PAGE_SIZE as i64).is_none(),
+    );
+
+    println!("Memory test: {} failures", failures);
+
+    cpu::exit_thread(failures);
+}
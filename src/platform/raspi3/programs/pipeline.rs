@@ -0,0 +1,62 @@
+use crate::aarch64::cpu;
+use crate::println;
+use alloc::vec::Vec;
+
+const NUMBERS: u64 = 100;
+const WORKERS: usize = 3;
+
+/// Sends numbers through a channel to workers that square them into a second channel, the sum of
+/// the squares is checked at the end. Exits with 0 if it matches.
+pub extern "C" fn pipeline(_: usize) {
+    // The ends are opened before the workers start, so that no worker sees the end of a stream
+    // that has not started yet
+    let numbers = cpu::open_object("chan:numbers:w:4");
+    let squares = cpu::open_object("chan:squares:r");
+
+    let workers: Vec<u64> = (0..WORKERS)
+        .map(|i| cpu::create_thread(square_worker, alloc::format!("Square {}", i), 0))
+        .collect();
+
+    for number in 1..=NUMBERS {
+        cpu::write_object(numbers, &number.to_le_bytes());
+    }
+
+    cpu::close_object(numbers);
+
+    let mut sum = 0;
+    let mut buffer = [0; 8];
+
+    while cpu::read_object(squares, &mut buffer) == buffer.len() {
+        sum += u64::from_le_bytes(buffer);
+    }
+
+    cpu::close_object(squares);
+
+    for worker in workers {
+        cpu::join_thread(worker);
+    }
+
+    let expected = NUMBERS * (NUMBERS + 1) * (2 * NUMBERS + 1) / 6;
+
+    println!("Sum of squares: {} (expected {})", sum, expected);
+
+    cpu::exit_thread((sum != expected) as u64);
+}
+
+extern "C" fn square_worker(_: usize) {
+    let numbers = cpu::open_object("chan:numbers:r");
+    let squares = cpu::open_object("chan:squares:w");
+
+    let mut buffer = [0; 8];
+
+    while cpu::read_object(numbers, &mut buffer) == buffer.len() {
+        let number = u64::from_le_bytes(buffer);
+
+        cpu::write_object(squares, &(number * number).to_le_bytes());
+    }
+
+    cpu::close_object(numbers);
+    cpu::close_object(squares);
+
+    cpu::exit_thread(0);
+}
@@ -1,15 +1,106 @@
 use crate::aarch64::cpu;
-use crate::elf::{ELF64Header, ProgramHeader};
+use crate::elf::ElfFile;
 use crate::platform::platform_devices::PLATFORM;
 use crate::print;
 use crate::println;
 use alloc::vec;
-use alloc::vec::Vec;
 
+const FILE: &str = "file:USERS./MOE./EXIT.ELF";
+
+/// Largest file that is read, the whole file has to be in memory to parse the sections
+const MAX_FILE_SIZE: usize = 0x10000;
+
+/// Prints the headers, sections and symbols of an ELF file, like readelf -hlSs
 pub extern "C" fn readelf(_: usize) {
-    println!("Running Exit.elf");
+    let handle = cpu::open_object(FILE);
+
+    let mut buffer = vec![0; MAX_FILE_SIZE];
+    let bytes_read = cpu::read_object(handle, &mut buffer);
+
+    cpu::close_object(handle);
+
+    let elf = match ElfFile::parse(&buffer[..bytes_read]) {
+        Ok(elf) => elf,
+        Err(error) => {
+            println!("{}: {}", FILE, error);
+            cpu::exit_thread(1);
+            return;
+        }
+    };
+
+    println!("ELF header:");
+    println!("  Type:    {:?}", elf.header.object_file_type);
+    println!("  Machine: {}", elf.header.e_machine);
+    println!("  Entry:   {:#x}", elf.header.program_entry_address);
+
+    println!();
+    println!("Program headers:");
+    println!(
+        "  {:<12} {:>10} {:>18} {:>10} {:>10} Flags",
+        "Type", "Offset", "VirtAddr", "FileSiz", "MemSiz"
+    );
+
+    for header in elf.program_headers() {
+        println!(
+            "  {:<12} {:#10x} {:#18x} {:#10x} {:#10x} {}{}{}",
+            alloc::format!("{:?}", header.program_type),
+            header.offset,
+            header.virtual_address,
+            header.file_size,
+            header.memory_size,
+            if header.is_readable() { 'R' } else { ' ' },
+            if header.is_writable() { 'W' } else { ' ' },
+            if header.is_executable() { 'E' } else { ' ' },
+        );
+    }
+
+    println!();
+    println!("Section headers:");
+    println!(
+        "  [Nr] {:<18} {:<18} {:>18} {:>10} {:>10}",
+        "Name", "Type", "Address", "Offset", "Size"
+    );
+
+    for (i, (section, name)) in elf.sections().enumerate() {
+        println!(
+            "  [{:2}] {:<18} {:<18} {:#18x} {:#10x} {:#10x}",
+            i,
+            name.unwrap_or("<invalid>"),
+            alloc::format!("{:?}", section.section_type),
+            section.address,
+            section.offset,
+            section.size,
+        );
+    }
+
+    for (title, table) in [
+        (".symtab", elf.symbol_table()),
+        (".dynsym", elf.dynamic_symbol_table()),
+    ] {
+        let table = match table {
+            Some(table) => table,
+            None => continue,
+        };
+
+        println!();
+        println!("Symbol table '{}' contains {} entries:", title, table.len());
+        println!(
+            "  {:>4} {:>18} {:>6} {:<8} {:<8} Name",
+            "Num", "Value", "Size", "Type", "Bind"
+        );
 
-    PLATFORM.exec("file:USERS./MOE./EXIT.ELF");
+        for (i, (symbol, name)) in table.iter().enumerate() {
+            println!(
+                "  {:>4} {:#18x} {:>6} {:<8} {:<8} {}",
+                i,
+                symbol.value,
+                symbol.size,
+                alloc::format!("{:?}", symbol.symbol_type()),
+                alloc::format!("{:?}", symbol.binding()),
+                name.unwrap_or("<invalid>"),
+            );
+        }
+    }
 
     cpu::exit_thread(0);
 }
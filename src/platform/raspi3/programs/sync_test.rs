@@ -0,0 +1,205 @@
+use crate::aarch64::cpu;
+use crate::platform::barrier::Barrier;
+use crate::platform::condvar::Condvar;
+use crate::platform::rwlock::RwLock;
+use crate::platform::semaphore::SemMutex;
+use crate::println;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+const WORKERS: usize = 4;
+const ITEMS: u64 = 1_000;
+const ITERATIONS: u64 = 2_000;
+const PHASES: usize = 10;
+
+/// Starts a worker thread for each argument and joins them all. The arguments are pointers that
+/// the workers take ownership of.
+fn run_workers<T>(
+    name: &str,
+    worker: extern "C" fn(arguments: T),
+    arguments: impl Iterator<Item = usize>,
+) {
+    let threads: Vec<u64> = arguments
+        .enumerate()
+        .map(|(i, arguments)| {
+            cpu::create_thread(worker, alloc::format!("{} {}", name, i), arguments)
+        })
+        .collect();
+
+    for thread in threads {
+        cpu::join_thread(thread);
+    }
+}
+
+struct Queue {
+    items: SemMutex<VecDeque<u64>>,
+    not_empty: Condvar,
+}
+
+/// Consumers sleep on the condition variable until the producer has queued an item
+fn test_condvar() -> bool {
+    let queue = Arc::new(Queue {
+        items: SemMutex::new(VecDeque::new()),
+        not_empty: Condvar::new(),
+    });
+    let total = Arc::new(AtomicU64::new(0));
+
+    let consumers: Vec<u64> = (0..WORKERS)
+        .map(|i| {
+            cpu::create_thread(
+                consumer_thread,
+                alloc::format!("Consumer {}", i),
+                Box::into_raw(Box::new((queue.clone(), total.clone()))) as usize,
+            )
+        })
+        .collect();
+
+    for item in 1..=ITEMS {
+        queue.items.lock().push_back(item);
+        queue.not_empty.notify_one();
+    }
+
+    // Zeros tell the consumers to stop
+    for _ in 0..WORKERS {
+        queue.items.lock().push_back(0);
+        queue.not_empty.notify_one();
+    }
+
+    for consumer in consumers {
+        cpu::join_thread(consumer);
+    }
+
+    total.load(Ordering::SeqCst) == ITEMS * (ITEMS + 1) / 2
+}
+
+extern "C" fn consumer_thread(arguments: Box<(Arc<Queue>, Arc<AtomicU64>)>) {
+    let (queue, total) = *arguments;
+
+    loop {
+        let mut items = queue
+            .not_empty
+            .wait_while(queue.items.lock(), |items| items.is_empty());
+
+        match items.pop_front() {
+            Some(0) | None => break,
+            Some(item) => total.fetch_add(item, Ordering::SeqCst),
+        };
+    }
+
+    cpu::exit_thread(0);
+}
+
+struct Pair {
+    values: RwLock<(u64, u64)>,
+    torn_reads: AtomicU64,
+}
+
+/// Writers change both values together, so readers must never see them differ
+fn test_rwlock() -> bool {
+    let pair = Arc::new(Pair {
+        values: RwLock::new((0, 0)),
+        torn_reads: AtomicU64::new(0),
+    });
+
+    run_workers(
+        "RwLock",
+        rwlock_thread,
+        (0..WORKERS).map(|i| Box::into_raw(Box::new((pair.clone(), i % 2 == 0))) as usize),
+    );
+
+    let values = *pair.values.read();
+    let writes = ITERATIONS * WORKERS.div_ceil(2) as u64;
+
+    pair.torn_reads.load(Ordering::SeqCst) == 0 && values == (writes, writes)
+}
+
+extern "C" fn rwlock_thread(arguments: Box<(Arc<Pair>, bool)>) {
+    let (pair, is_writer) = *arguments;
+
+    for _ in 0..ITERATIONS {
+        if is_writer {
+            let mut values = pair.values.write();
+
+            values.0 += 1;
+            cpu::yield_thread();
+            values.1 += 1;
+        } else {
+            let values = pair.values.read();
+
+            if values.0 != values.1 {
+                pair.torn_reads.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    cpu::exit_thread(0);
+}
+
+struct Phases {
+    barrier: Barrier,
+    arrivals: [AtomicU64; PHASES],
+    leaders: AtomicU64,
+    early_departures: AtomicU64,
+}
+
+/// No worker may start a phase before every worker has finished the previous one
+fn test_barrier() -> bool {
+    let phases = Arc::new(Phases {
+        barrier: Barrier::new(WORKERS as u64),
+        arrivals: core::array::from_fn(|_| AtomicU64::new(0)),
+        leaders: AtomicU64::new(0),
+        early_departures: AtomicU64::new(0),
+    });
+
+    run_workers(
+        "Barrier",
+        barrier_thread,
+        (0..WORKERS).map(|_| Arc::into_raw(phases.clone()) as usize),
+    );
+
+    phases.early_departures.load(Ordering::SeqCst) == 0
+        && phases.leaders.load(Ordering::SeqCst) == PHASES as u64
+}
+
+extern "C" fn barrier_thread(phases: *const Phases) {
+    let phases = unsafe { Arc::from_raw(phases) };
+
+    for phase in 0..PHASES {
+        phases.arrivals[phase].fetch_add(1, Ordering::SeqCst);
+
+        if phases.barrier.wait() {
+            phases.leaders.fetch_add(1, Ordering::SeqCst);
+        }
+
+        if phases.arrivals[phase].load(Ordering::SeqCst) != WORKERS as u64 {
+            phases.early_departures.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    cpu::exit_thread(0);
+}
+
+/// Checks the blocking synchronisation primitives with several threads each. Exits with the
+/// number of failed checks.
+pub extern "C" fn sync_test(_: usize) {
+    let mut failures = 0;
+
+    for (name, test) in [
+        ("Condvar", test_condvar as fn() -> bool),
+        ("RwLock", test_rwlock),
+        ("Barrier", test_barrier),
+    ] {
+        let passed = test();
+
+        println!("{}: {}", name, if passed { "ok" } else { "FAILED" });
+
+        if !passed {
+            failures += 1;
+        }
+    }
+
+    cpu::exit_thread(failures);
+}
@@ -0,0 +1,94 @@
+use crate::aarch64::cpu;
+use crate::platform::thread::{ThreadInfo, ThreadStatus};
+use crate::platform::wait_queue::WaitQueueID;
+use crate::println;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+/// Time between the two snapshots the CPU usage is computed from, in microseconds
+const INTERVAL: u64 = 1_000_000;
+
+fn cpu_time(thread: &ThreadInfo) -> u64 {
+    thread.stats.user_time + thread.stats.kernel_time
+}
+
+/// Prints the threads with their share of the CPU time over the last interval, like top.
+/// The argument is the number of times to refresh, 0 refreshes forever.
+pub extern "C" fn top(iterations: usize) {
+    let mut previous = cpu::list_threads();
+    let mut iteration = 0;
+
+    while iterations == 0 || iteration < iterations {
+        cpu::sleep(INTERVAL);
+
+        let threads = cpu::list_threads();
+
+        // Threads that started during the interval count from zero
+        let elapsed: Vec<u64> = threads
+            .iter()
+            .map(|thread| {
+                let before = previous
+                    .iter()
+                    .find(|old| old.id == thread.id)
+                    .map_or(0, cpu_time);
+
+                cpu_time(thread).saturating_sub(before)
+            })
+            .collect();
+
+        // Idle threads are included, so this is the time of all cores together
+        let total = elapsed.iter().sum::<u64>().max(1);
+
+        println!(
+            "{:>4} {:>6} {:<16} {:<12} {:>4} {:>6} {:>10} {:>10} {:>8} {:>10}",
+            "ID",
+            "Parent",
+            "Name",
+            "Status",
+            "Prio",
+            "CPU%",
+            "User ms",
+            "Kernel ms",
+            "Switches",
+            "Wake us"
+        );
+
+        for (thread, elapsed) in threads.iter().zip(elapsed) {
+            let status = match thread.status {
+                ThreadStatus::Running => "Running",
+                ThreadStatus::Ready => "Ready",
+                ThreadStatus::Waiting(_) => "Sleeping",
+                ThreadStatus::Exited(_) => "Exited",
+                ThreadStatus::Blocked(WaitQueueID::Join(_)) => "Joining",
+                ThreadStatus::Blocked(_) => "Blocked",
+            };
+
+            let stats = &thread.stats;
+            let average_latency = stats.total_wake_up_latency / stats.wake_ups.max(1);
+
+            println!(
+                "{:>4} {:>6} {:<16} {:<12} {:>4} {:>4}.{} {:>10} {:>10} {:>8} {:>10}",
+                thread.id,
+                thread
+                    .parent
+                    .map_or("-".to_string(), |id| alloc::format!("{}", id)),
+                thread.name(),
+                status,
+                thread.priority,
+                elapsed * 100 / total,
+                elapsed * 1000 / total % 10,
+                stats.user_time / 1000,
+                stats.kernel_time / 1000,
+                stats.context_switches,
+                average_latency,
+            );
+        }
+
+        println!("");
+
+        previous = threads;
+        iteration += 1;
+    }
+
+    cpu::exit_thread(0);
+}
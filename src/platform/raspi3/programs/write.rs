@@ -5,7 +5,7 @@ use crate::println;
 pub extern "C" fn write(_: usize) {
     println!("Running Write.elf");
 
-    PLATFORM.exec("file:USERS./MOE./WRITE.ELF");
+    PLATFORM.exec("file:USERS./MOE./WRITE.ELF", &["write.elf"], &[]);
 
     cpu::exit_thread(0);
 }
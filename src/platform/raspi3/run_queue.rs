@@ -0,0 +1,103 @@
+//! A multi-level run queue for the scheduler
+//!
+//! Every priority level is a round robin queue and the highest non-empty level always runs first.
+//! To keep low priority threads from starving, threads that have been passed over for
+//! `AGING_INTERVAL` selections move up a level. They drop back to their own priority once they
+//! are queued again.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+use super::thread::{Priority, Thread, PRIORITY_LEVELS};
+
+/// Number of selections a thread waits before being promoted to the next level
+const AGING_INTERVAL: u64 = 8;
+
+struct QueuedThread<'a> {
+    thread: Arc<Thread<'a>>,
+    /// Selection count at the time the thread was queued or last promoted
+    queued_at: u64,
+}
+
+pub struct RunQueue<'a> {
+    levels: [VecDeque<QueuedThread<'a>>; PRIORITY_LEVELS],
+    selections: u64,
+}
+
+impl<'a> RunQueue<'a> {
+    pub fn new() -> Self {
+        Self {
+            levels: core::array::from_fn(|_| VecDeque::new()),
+            selections: 0,
+        }
+    }
+
+    /// Queues the thread behind all threads of its priority
+    pub fn push_back(&mut self, thread: Arc<Thread<'a>>) {
+        let level = thread.priority() as usize;
+
+        self.levels[level].push_back(QueuedThread {
+            thread,
+            queued_at: self.selections,
+        });
+    }
+
+    /// Takes the next thread from the highest non-empty level
+    pub fn pop_front(&mut self) -> Option<Arc<Thread<'a>>> {
+        self.selections += 1;
+        self.age();
+
+        self.levels
+            .iter_mut()
+            .rev()
+            .find_map(|level| level.pop_front())
+            .map(|queued| queued.thread)
+    }
+
+    /// Moves threads that have waited for too long up a level
+    fn age(&mut self) {
+        // Going from high to low promotes every thread by at most one level per selection
+        for level in (0..PRIORITY_LEVELS - 1).rev() {
+            while self.levels[level]
+                .front()
+                .is_some_and(|queued| self.selections - queued.queued_at >= AGING_INTERVAL)
+            {
+                let mut queued = self.levels[level].pop_front().unwrap();
+
+                queued.queued_at = self.selections;
+                self.levels[level + 1].push_back(queued);
+            }
+        }
+    }
+
+    /// The highest level with a queued thread
+    pub fn highest_priority(&self) -> Option<Priority> {
+        (0..PRIORITY_LEVELS)
+            .rev()
+            .find(|&level| !self.levels[level].is_empty())
+            .map(|level| level as Priority)
+    }
+
+    /// Removes the thread from the queue, returning whether it was queued
+    pub fn remove(&mut self, thread: &Arc<Thread<'a>>) -> bool {
+        for level in self.levels.iter_mut() {
+            if let Some(index) = level
+                .iter()
+                .position(|queued| Arc::ptr_eq(&queued.thread, thread))
+            {
+                level.remove(index);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.iter().map(|level| level.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.iter().all(|level| level.is_empty())
+    }
+}
@@ -0,0 +1,119 @@
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::aarch64::cpu;
+
+/// State of a lock that is held by a writer, otherwise the state is the number of readers
+const WRITER: u64 = u64::MAX;
+
+/// A lock that is held by any number of readers or by a single writer. Threads that cannot take
+/// the lock sleep in the kernel until it is released.
+#[derive(Debug)]
+pub struct RwLock<T> {
+    state: AtomicU64,
+    /// Number of threads that are about to sleep on the state or are sleeping on it
+    sleepers: AtomicU64,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicU64::new(0),
+            sleepers: AtomicU64::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+
+            if state == WRITER {
+                self.sleep(state);
+            } else if self
+                .state
+                .compare_exchange(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return RwLockReadGuard { lock: self };
+            }
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            match self
+                .state
+                .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => return RwLockWriteGuard { lock: self },
+                Err(state) => self.sleep(state),
+            }
+        }
+    }
+
+    /// Sleeps until the state changes. The kernel only puts the thread to sleep if it still holds
+    /// the state that was seen.
+    fn sleep(&self, state: u64) {
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        cpu::futex_wait(&self.state, state);
+        self.sleepers.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Wakes all sleepers, readers can all take the lock and writers race for it
+    fn wake_sleepers(&self) {
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            cpu::futex_wake(&self.state, usize::MAX);
+        }
+    }
+}
+
+impl<'a, T> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        // Only writers wait for readers, and they need all of them to be gone
+        if self.lock.state.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.lock.wake_sleepers();
+        }
+    }
+}
+
+impl<'a, T> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::SeqCst);
+        self.lock.wake_sleepers();
+    }
+}
@@ -9,27 +9,40 @@ use crate::{aarch64::cpu, sync::SpinMutexGuard};
 #[derive(Debug)]
 struct Semaphore {
     value: AtomicU64,
+    /// Number of threads that are about to sleep on the value or are sleeping on it
+    sleepers: AtomicU64,
 }
 
 impl Semaphore {
-    const ORDERING: Ordering = Ordering::Relaxed;
+    // Waiting acquires and signalling releases, so that the protected data is visible to the next
+    // holder even if it runs on another core. Signalling is also ordered with the check for
+    // sleepers, otherwise a signal could miss a thread that is going to sleep.
+    const WAIT_ORDERING: Ordering = Ordering::Acquire;
+    const SIGNAL_ORDERING: Ordering = Ordering::SeqCst;
 
     pub const fn new(value: u64) -> Self {
         Self {
             value: AtomicU64::new(value),
+            sleepers: AtomicU64::new(0),
         }
     }
 
     pub fn wait(&self) {
         loop {
-            let value = self.value.load(Self::ORDERING);
+            let value = self.value.load(Ordering::Relaxed);
 
             if value == 0 {
-                cpu::yield_thread(); // TODO: have this thread sleep until increment
+                // The kernel only puts the thread to sleep if there was no signal in the meantime
+                self.sleepers.fetch_add(1, Ordering::SeqCst);
+                cpu::futex_wait(&self.value, 0);
+                self.sleepers.fetch_sub(1, Ordering::Relaxed);
             } else {
-                let result =
-                    self.value
-                        .compare_exchange(value, value - 1, Self::ORDERING, Self::ORDERING);
+                let result = self.value.compare_exchange(
+                    value,
+                    value - 1,
+                    Self::WAIT_ORDERING,
+                    Ordering::Relaxed,
+                );
 
                 if !result.is_err() {
                     return;
@@ -40,16 +53,20 @@ impl Semaphore {
 
     pub fn signal(&self) {
         loop {
-            let value = self.value.load(Self::ORDERING);
+            let value = self.value.load(Ordering::Relaxed);
 
             if self
                 .value
-                .compare_exchange(value, value + 1, Self::ORDERING, Self::ORDERING)
+                .compare_exchange(value, value + 1, Self::SIGNAL_ORDERING, Ordering::Relaxed)
                 .is_ok()
             {
-                return;
+                break;
             }
         }
+
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            cpu::futex_wake(&self.value, 1);
+        }
     }
 }
 
@@ -59,9 +76,11 @@ pub struct SemMutex<T> {
     data: UnsafeCell<T>,
 }
 
+// The semaphore lets only one thread at a time reach the data
+unsafe impl<T: Send> Sync for SemMutex<T> {}
+
 pub struct SemMutexGuard<'a, T> {
-    semaphore: &'a Semaphore,
-    data: &'a mut T,
+    mutex: &'a SemMutex<T>,
 }
 
 impl<'a, T> SemMutex<T> {
@@ -75,36 +94,40 @@ impl<'a, T> SemMutex<T> {
     pub fn lock(&'a self) -> SemMutexGuard<'a, T> {
         self.semaphore.wait();
 
-        SemMutexGuard {
-            semaphore: &self.semaphore,
-            data: unsafe { &mut *self.data.get() },
-        }
+        SemMutexGuard { mutex: self }
     }
 
     pub fn execute(&self, f: impl FnOnce(&T)) {
-        f(self.lock().data);
+        f(&self.lock());
     }
 
     pub fn execute_mut(&mut self, f: impl FnOnce(&mut T)) {
-        f(self.lock().data);
+        f(&mut self.lock());
+    }
+}
+
+impl<'a, T> SemMutexGuard<'a, T> {
+    /// The mutex the guard holds, so that it can be locked again after the guard is dropped
+    pub fn mutex(guard: &Self) -> &'a SemMutex<T> {
+        guard.mutex
     }
 }
 
 impl<'a, T> Deref for SemMutexGuard<'a, T> {
     type Target = T;
     fn deref(&self) -> &T {
-        self.data
+        unsafe { &*self.mutex.data.get() }
     }
 }
 
 impl<'a, T> DerefMut for SemMutexGuard<'a, T> {
     fn deref_mut(&mut self) -> &mut T {
-        self.data
+        unsafe { &mut *self.mutex.data.get() }
     }
 }
 
 impl<'a, T> Drop for SemMutexGuard<'a, T> {
     fn drop(&mut self) {
-        self.semaphore.signal();
+        self.mutex.semaphore.signal();
     }
 }
@@ -0,0 +1,62 @@
+//! Bring up of the secondary cores
+//!
+//! The firmware parks cores 1-3 in a loop that waits for an entry address in the spin table at
+//! 0xd8 and jumps to it. When the kernel is loaded as an ELF every core enters `_start` instead,
+//! which parks the other cores the same way. They are released once the kernel is running.
+
+use core::arch::asm;
+
+use crate::aarch64::{cpu, interrupt};
+use crate::platform::platform_devices::PLATFORM;
+use crate::println;
+
+/// Number of cores of the BCM2837
+pub const CORES: usize = 4;
+
+/// Spin table entry of core 0, the entries of the other cores follow it. Accessed through the
+/// kernel mapping of low memory.
+const SPIN_TABLE_START: usize = 0xFFFF_0000_0000_00D8;
+
+/// Releases cores 1-3 from the spin table. The kernel has to be registered with the platform,
+/// as each core adds itself to the scheduler.
+pub fn start_secondary_cores() {
+    let entry_point: u64;
+
+    // The entry point in start.s is linked at its physical address, out of reach of adrp from the
+    // upper half, so its address is loaded from a literal
+    unsafe {
+        asm!("ldr {}, =_start_secondary", out(reg) entry_point);
+    }
+
+    for core in 1..CORES {
+        let entry = (SPIN_TABLE_START + 8 * core) as *mut u64;
+
+        unsafe {
+            core::ptr::write_volatile(entry, entry_point);
+
+            // The waiting cores read the entry with their MMU and caches off
+            asm!("dc civac, {}", in(reg) entry);
+        }
+    }
+
+    unsafe {
+        asm!("dsb sy", "sev");
+    }
+}
+
+/// Called by start.s on each secondary core, once it runs at EL1 on its own stack with the kernel
+/// tables loaded
+#[no_mangle]
+pub extern "C" fn secondary_main() -> ! {
+    PLATFORM.register_core();
+    PLATFORM.enable_core_interrupts();
+
+    println!("Core {} started", cpu::core_id());
+
+    interrupt::enable_irq();
+
+    // This is the core's idle thread
+    loop {
+        cpu::wait_for_interrupt();
+    }
+}
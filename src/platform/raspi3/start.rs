@@ -1,10 +1,9 @@
 use super::kernel::Kernel;
-use super::kernel::TICK;
 use super::programs::ls;
-use super::programs::{counter, readelf, write};
+use super::programs::{counter, memory_test, pipeline, readelf, sync_test, top, write};
 use crate::aarch64::interrupt::IRQLock;
-use crate::aarch64::{cpu, interrupt, mmu, syscall::Syscall};
-use crate::allocator::page_allocator::PageAllocator;
+use crate::aarch64::{backtrace::KernelSymbols, cpu, interrupt, mmu, syscall::Syscall};
+use crate::allocator::buddy_alloc::{BuddyAllocator, PAGE_ALLOCATOR};
 use crate::canvas::{canvas2d::Canvas2D, line::Line, matrix::Matrix, vector::Vector};
 use crate::ALLOCATOR;
 use crate::{print, println, read, write};
@@ -36,8 +35,10 @@ use super::{
     hardware_config::HardwareConfig,
     interrupt::InterruptController,
     mailbox::{Channel, MailboxController},
+    mmio,
     platform_devices::{get_platform, PLATFORM},
     power::{Device, PowerState, DEVICES},
+    smp,
 };
 
 unsafe extern "C" {
@@ -47,6 +48,9 @@ unsafe extern "C" {
 
 global_asm!(include_str!("start.s"));
 
+/// Copy of the unstripped kernel on the boot partition, see `make copy-symbols`
+const KERNEL_SYMBOL_FILE: &str = "KERNEL.ELF";
+
 #[no_mangle]
 pub extern "C" fn main(heap_start: usize, heap_size: usize, table_start: usize) {
     ALLOCATOR.lock().init(heap_start, heap_size);
@@ -60,13 +64,15 @@ pub extern "C" fn main(heap_start: usize, heap_size: usize, table_start: usize)
     println!("Starting");
 
     println!("Entering Boot Sequence (with new build system?)");
-    println!("Initializing Memory Virtualization");
+    println!("Checking Memory Virtualization");
+
+    let device_address = mmio::get_timer_registers() as *mut _ as usize;
 
-    /*unsafe {
-        mmu::init(table_start as *mut usize);
-    };*/
+    if let Err(error) = mmu::self_check(table_start, device_address) {
+        panic!("Memory virtualization self check failed: {}", error);
+    }
 
-    println!("Memory Virtualization Initialized");
+    println!("Memory Virtualization Checked");
 
     println!(
         "Heap Allocator initialized at {:#x} with size {}",
@@ -111,7 +117,7 @@ pub extern "C" fn main(heap_start: usize, heap_size: usize, table_start: usize)
 
     let partition = master_boot_record.partition_entries[0];
 
-    let filesystem = FAT32Filesystem::load_in_partition(
+    let mut filesystem = FAT32Filesystem::load_in_partition(
         emmc_controller,
         mbr_sector_number + partition.first_sector_address(),
         mbr_sector_number + partition.last_sector_address(),
@@ -122,7 +128,23 @@ pub extern "C" fn main(heap_start: usize, heap_size: usize, table_start: usize)
 
     println!("Root directory: {}", root_dir);
 
-    let page_allocator: IRQLock<PageAllocator>;
+    // Symbols for backtraces, the image that is booted is stripped
+    match filesystem.search_item(KERNEL_SYMBOL_FILE) {
+        Some(entry) => match KernelSymbols::from_elf(|offset, buffer| {
+            filesystem.read_file_at(entry, offset, buffer)
+        }) {
+            Ok(symbols) => {
+                println!("Loaded {} kernel symbols", symbols.len());
+                PLATFORM.register_symbols(symbols);
+            }
+            Err(error) => {
+                println!("Unable to load kernel symbols: {}", error);
+            }
+        },
+        None => {
+            println!("No kernel symbols found at {}", KERNEL_SYMBOL_FILE);
+        }
+    }
 
     unsafe {
         let page_start: usize = &PAGE_SECTION_START as *const usize as usize;
@@ -132,11 +154,21 @@ pub extern "C" fn main(heap_start: usize, heap_size: usize, table_start: usize)
             page_start, page_size
         );
 
-        page_allocator = IRQLock::new(PageAllocator::with_start_and_length(page_start, page_size));
+        *PAGE_ALLOCATOR.lock() = Some(BuddyAllocator::with_start_and_length(page_start, page_size));
     }
 
-    let kernel =
-        Kernel::with_page_allocator_and_filesystem(page_allocator, IRQLock::new(filesystem));
+    // Copied out, as printing may allocate slab pages
+    let page_stats = PAGE_ALLOCATOR.lock().as_ref().map(BuddyAllocator::stats);
+
+    if let Some(page_stats) = page_stats {
+        println!(
+            "Page allocator manages {} pages in {} blocks",
+            page_stats.total_pages,
+            page_stats.free_blocks.iter().sum::<usize>()
+        );
+    }
+
+    let kernel = Kernel::with_filesystem(IRQLock::new(filesystem));
 
     PLATFORM.register_kernel(kernel);
 
@@ -150,26 +182,38 @@ pub extern "C" fn main(heap_start: usize, heap_size: usize, table_start: usize)
     interrupt_controller.enable_timer_interrupt_1();
     interrupt_controller.enable_auxiliary_device_interrupts();
 
+    PLATFORM.enable_core_interrupts();
+
     println!("Timer interrupt enabled!");
 
+    smp::start_secondary_cores();
+
     //cpu::create_thread(graphics_thread, String::from("Graphics"), 0);
 
     //cpu::create_thread(long_count, String::from("Long Count"), 0);
 
     //cpu::create_thread(counter::run_count, String::from("Counters"), 20);
 
+    //cpu::create_thread(sync_test::sync_test, String::from("Sync test"), 0);
+
+    //cpu::create_thread(pipeline::pipeline, String::from("Pipeline"), 0);
+
+    //cpu::create_thread(memory_test::memory_test, String::from("Memory test"), 0);
+
     //cpu::create_thread(readelf::readelf, String::from("readelf"), 0);
 
+    //cpu::create_thread(top::top, String::from("top"), 0);
+
     cpu::create_thread(write::write, String::from("write"), 0);
 
     //cpu::create_thread(ls::ls, String::from("ls"), 0);
 
-    PLATFORM.set_kernel_timeout(TICK);
-
     //status_light.borrow_mut().set_green(OutputLevel::High);
 
+    // This is core 0's idle thread, the scheduler switches away from it as soon as a thread is
+    // ready
     loop {
-        cpu::yield_thread();
+        cpu::wait_for_interrupt();
     }
 }
 
@@ -209,6 +253,8 @@ pub extern "C" fn graphics_thread(_arg: usize) {
             fb.write_idx(i, 0xff00ffff);
         }
 
+        fb.flush();
+
         for j in 0..1920 {
             for i in 0..1080 {
                 fb.write_pixel(
@@ -218,6 +264,8 @@ pub extern "C" fn graphics_thread(_arg: usize) {
                 );
             }
         }
+
+        fb.flush();
     }
 }
 
@@ -1,20 +1,7 @@
 .section ".text.boot"
 
-.global _start
-
-_start: //spin if not main core
-    mrs     x1, mpidr_el1 // the mpidr_el1 register contains the core id for each core in bits 0-7
-    and     x1, x1, 0b11 // since there are only 4 cores, bits 0-1 will suffice
-    cbz     x1, 2f // branch to 2f if the core id is 0
-
-1:  wfe
-    b       1b // otherwise, spin
-
-2:  // cpu id == 0
-
-    // set top of stack just before our code (stack grows to a lower address per AAPCS64)
-    //ldr     x1, =_start
-
+// Drops from EL3 or EL2 to EL1, continuing after the macro
+.macro enter_el1
     // detect the current el
     mrs     x0, CurrentEL // CurrentEL stores the exception level in bits 3-2
     and     x0, x0, 0b1100
@@ -109,9 +96,33 @@ _start: //spin if not main core
     msr     elr_el2, x2
     
     eret
+5:
+.endm
+
+.global _start
+
+_start: //spin if not main core
+    mrs     x1, mpidr_el1 // the mpidr_el1 register contains the core id for each core in bits 0-7
+    and     x1, x1, 0b11 // since there are only 4 cores, bits 0-1 will suffice
+    cbz     x1, 2f // branch to 2f if the core id is 0
+
+    // otherwise, wait for an entry in the spin table like the firmware does (see smp.rs)
+    mov     x2, 0xd8
+    add     x2, x2, x1, lsl #3 // the entry of core n is at 0xd8 + 8n
+1:  wfe
+    ldr     x3, [x2]
+    cbz     x3, 1b
+    br      x3
+
+2:  // cpu id == 0
+
+    // set top of stack just before our code (stack grows to a lower address per AAPCS64)
+    //ldr     x1, =_start
+
+    enter_el1
 
     //set stack pointer
-5:  ldr     x0, =_start
+    ldr     x0, =_start
     mov     sp, x0
 
 
@@ -126,47 +137,99 @@ _start: //spin if not main core
     ldr x0, =VM_START
     add sp, sp, x0
 
+    bl enable_mmu
+
+    ldr     x0, =HEAP_START
+    ldr     x1, =HEAP_SIZE
+    ldr     x2, =USER_TABLE_START
+
+    ldr x5, =main
+
+    // clear bss
+    ldr     x7, =bss_start
+    ldr     w8, =bss_size
+3:  cbz     w8, 9f
+    str     xzr, [x7], #8
+    sub     w8, w8, #1
+    cbnz    w8, 3b
+
+9:
+    br       x5
+
+// Entry point of cores 1-3 once they are released from the spin table by start_secondary_cores
+.global _start_secondary
+
+_start_secondary:
+    enter_el1
+
+    ldr     x0, =_exception_vector
+    msr     VBAR_EL1, x0
+
+    // The tables have already been created by core 0
+    bl enable_mmu
+
+    // Each core has its own stack, core n uses the n-th slot above CORE_STACKS_START
+    mrs     x0, mpidr_el1
+    and     x0, x0, 0b11
+    ldr     x1, =CORE_STACKS_START
+    ldr     x2, =CORE_STACK_SIZE
+    madd    x1, x0, x2, x1
+    mov     sp, x1
+
+    ldr     x5, =secondary_main
+    br      x5
+
+// Loads the kernel and user tables and enables memory translation on the calling core
+enable_mmu:
     adrp x0, KERNEL_TABLE_START
     msr ttbr1_el1, x0
 
     adrp x0, USER_TABLE_START
     msr ttbr0_el1, x0
 
-    ldr x0, =((64 - 48) | ((64 - 48) << 16) | (0 << 14) | (2 << 30) | (0b101 << 32))
+    /*
+        Translation Control Register, the same for ttbr0 and ttbr1
+        T0SZ/T1SZ: 48 bit address spaces
+        IRGN/ORGN: 01 table walks are write-back cacheable, like the tables written by the kernel
+        SH: 11 tables are inner shareable, so the walks of all cores see the same tables
+        TG0/TG1: 4KB granules
+    */
+    ldr x0, =((64 - 48) | (0b01 << 8) | (0b01 << 10) | (0b11 << 12) | (0 << 14) | ((64 - 48) << 16) | (0b01 << 24) | (0b01 << 26) | (0b11 << 28) | (2 << 30) | (0b101 << 32))
     msr tcr_el1, x0
 
-    ldr x0, =(0x00 << (8 * 0x0) | (0x44 << (8 * 0x1)))
+    /*
+        Memory Attribute Indirection Register, must match the constants in mmu.rs
+        Attr0: 0x00 Device-nGnRnE for the peripherals, mmu::DEVICE_ATTRIBUTES at mmu::DEVICE_ATTRIBUTE_INDEX
+        Attr1: 0xFF Normal memory, inner and outer write-back read/write-allocate, for RAM,
+               mmu::NORMAL_ATTRIBUTES at mmu::NORMAL_ATTRIBUTE_INDEX
+    */
+    ldr x0, =(0x00 << (8 * 0x0) | (0xFF << (8 * 0x1)))
     msr mair_el1, x0
 
-
-    ldr     x0, =HEAP_START
-    ldr     x1, =HEAP_SIZE
-    ldr     x2, =USER_TABLE_START
-
-    ldr x5, =main
-
     isb
     dsb sy
 
-    // Enable memory translation
+    /*
+        System Control Register
+        [0]: M: 1 enables memory translation
+        [2]: C: 1 enables the data caches
+        [12]: I: 1 enables the instruction cache
+    */
     mrs x4, sctlr_el1
     orr x4, x4, #1
+    orr x4, x4, #(1 << 2)
+    orr x4, x4, #(1 << 12)
     //orr x0, x0, #(1 << 25) Do we need to deal with endianness?
     msr sctlr_el1, x4
 
     isb
 
-    // clear bss
-    ldr     x7, =bss_start
-    ldr     w8, =bss_size
-3:  cbz     w8, 9f
-    str     xzr, [x7], #8
-    sub     w8, w8, #1
-    cbnz    w8, 3b
+    ret
 
-9:
-    br       x5
 
+// Block descriptor flags: valid block, attribute index, shareability and the access flag
+.equ NORMAL_BLOCK, (0x1 | (0x1 << 2) | (0b11 << 8) | (0x1 << 10))
+.equ DEVICE_BLOCK, (0x1 | (0x0 << 2) | (0x1 << 10))
 
 // Map a virtual address
 .macro create_table_entry, tbl, virt, shift, tmp1, tmp2
@@ -215,13 +278,23 @@ create_page_tables:
     ldr x2, =VM_START
 
     ldr x3, =0xffff00003ee00000
-    map_blocks x0, x1, x2, x3, (0x1 | (0x1 << 2) | (0x1 << 10)), x4
+    map_blocks x0, x1, x2, x3, NORMAL_BLOCK, x4
 
     // Map device memory
     ldr x1, =MMIO_START
     ldr x2, =VIRTUAL_MMIO_START
     ldr x3, =(0xffff000000000000 + 0x40000000 - 0x20000)
-    map_blocks x0, x1, x2, x3, (0x1 | (0x00 << 2) | (0x1 << 10)), x4
+    map_blocks x0, x1, x2, x3, DEVICE_BLOCK, x4
+
+    // Map the local peripherals (core timers and interrupt routing) in the second gigabyte
+    adrp x0, KERNEL_TABLE_START
+    add x1, x0, #(3 * 4096) // a new table after the pgd, pud and table from above
+    orr x2, x1, 0x3
+    str x2, [x0, #(4096 + 8)] // second entry of the pud
+    ldr x2, =LOCAL_PERIPHERALS_OFFSET
+    mov x3, #DEVICE_BLOCK
+    orr x2, x2, x3
+    str x2, [x1]
 
     ret
 
@@ -233,11 +306,11 @@ create_user_page_tables:
 
     mov x2, xzr
     mov x3, 0x3ee00000 // Total user memory. TODO: check this value
-    map_blocks x0, x1, x2, x3, (0x1 | (0x1 << 2) | (0x1 << 10)), x4
+    map_blocks x0, x1, x2, x3, NORMAL_BLOCK, x4
 
     ldr x1, = MMIO_START
     ldr x2, = MMIO_START
     ldr x3, =(0x40000000 - 0x20000)
-    map_blocks x0, x1, x2, x3, (0x1 | (0x00 << 2) | (0x1 << 10)), x4
+    map_blocks x0, x1, x2, x3, DEVICE_BLOCK, x4
 
     ret
\ No newline at end of file
@@ -1,6 +1,8 @@
 use alloc::rc::Rc;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use core::arch::asm;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 use alloc::vec;
 
@@ -12,12 +14,18 @@ use alloc::string::String;
 
 use alloc::boxed::Box;
 
-use super::kernel_object::{KernelObject, ObjectHandle};
+use super::kernel_object::{FileObject, InheritedHandles, KernelObject, ObjectHandle, Transfer};
+use super::loader::{
+    ElfLoader, ExecError, ProgramArguments, ProgramEntry, USER_ADDRESS_LIMIT, USER_STACK_PAGE,
+};
+use super::run_queue::RunQueue;
+use super::smp::CORES;
+use super::user_memory::{self, UserHeap};
+use super::wait_queue::{WaitQueueID, WaitQueues, FUTEX_ERROR, FUTEX_MISMATCH, FUTEX_WOKEN};
 use crate::aarch64::interrupt::IRQLock;
 use crate::aarch64::{cpu, mmu};
 use crate::allocator::page_allocator::PAGE_SIZE;
-use crate::elf::{ELF64Header, ProgramHeader, ProgramType};
-use crate::platform::page_table::PageTable;
+use crate::platform::page_table::{PagePermissions, PageTable};
 use crate::platform::platform_devices::PLATFORM;
 use crate::platform::raspi3::exception::InterruptFrame;
 
@@ -27,56 +35,169 @@ pub enum ThreadStatus {
     Ready,
     Waiting(u64),
     Exited(u64),
-    Joining(ThreadID),
+    /// Off the run queues until the wait queue is woken
+    Blocked(WaitQueueID),
 }
 
 pub type ThreadID = u64;
 
+/// Scheduling priority, higher values run first
+pub type Priority = u8;
+
+pub const PRIORITY_LEVELS: usize = 8;
+pub const LOWEST_PRIORITY: Priority = 0;
+pub const HIGHEST_PRIORITY: Priority = PRIORITY_LEVELS as Priority - 1;
+pub const DEFAULT_PRIORITY: Priority = 3;
+
+/// Returned by the priority syscalls for unknown threads or invalid priorities
+pub const PRIORITY_ERROR: u64 = u64::MAX;
+
+/// Returned by the kill syscall for unknown threads and threads the caller may not kill
+pub const KILL_ERROR: u64 = u64::MAX;
+
+/// Set in the exit code of threads that were killed by an exception they caused. The low 32 bits
+/// hold the syndrome, so that joiners can tell a crash apart from a regular exit.
+pub const EXIT_CODE_FAULT: u64 = 1 << 63;
+
+pub fn fault_exit_code(syndrome: u64) -> u64 {
+    EXIT_CODE_FAULT | (syndrome & 0xFFFF_FFFF)
+}
+
+/// Kernel stacks are blocks of 2^KERNEL_STACK_ORDER pages
+pub const KERNEL_STACK_ORDER: usize = 1;
+pub const KERNEL_STACK_SIZE: usize = PAGE_SIZE << KERNEL_STACK_ORDER;
+
+/// Longest thread name reported by [ThreadInfo], longer names are cut off
+pub const THREAD_NAME_LENGTH: usize = 32;
+
+/// CPU time accounting of a thread. Times are in microseconds of the system timer.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ThreadStats {
+    /// Time the thread ran outside of the kernel's exception handlers
+    pub user_time: u64,
+    /// Time the kernel spent handling exceptions while the thread was running
+    pub kernel_time: u64,
+    /// Number of times a core switched to the thread
+    pub context_switches: u64,
+    /// Number of times the thread became ready after it was created or had been waiting
+    pub wake_ups: u64,
+    /// Time from becoming ready to running, summed over all wake ups
+    pub total_wake_up_latency: u64,
+    pub max_wake_up_latency: u64,
+    /// When the thread last became ready, until it runs
+    pub ready_since: Option<u64>,
+}
+
+/// A snapshot of a thread, as returned by the thread listing syscall
+#[derive(Copy, Clone, Debug)]
+pub struct ThreadInfo {
+    pub id: ThreadID,
+    /// None for threads the kernel started itself, or whose parent is gone
+    pub parent: Option<ThreadID>,
+    pub status: ThreadStatus,
+    pub priority: Priority,
+    name: [u8; THREAD_NAME_LENGTH],
+    name_length: usize,
+    pub stats: ThreadStats,
+}
+
+impl ThreadInfo {
+    fn from_thread(thread: &Thread) -> Self {
+        // Cut the name off at a character boundary, so that it stays valid UTF-8
+        let name_length = (0..=thread.name.len().min(THREAD_NAME_LENGTH))
+            .rev()
+            .find(|&length| thread.name.is_char_boundary(length))
+            .unwrap_or(0);
+
+        let mut name = [0; THREAD_NAME_LENGTH];
+
+        name[..name_length].copy_from_slice(&thread.name.as_bytes()[..name_length]);
+
+        Self {
+            id: thread.id,
+            parent: thread
+                .parent
+                .as_ref()
+                .and_then(Weak::upgrade)
+                .map(|parent| parent.id),
+            status: *thread.status.lock(),
+            priority: thread.priority(),
+            name,
+            name_length,
+            stats: *thread.stats.lock(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_length]).unwrap_or("")
+    }
+}
+
 #[derive(Debug)]
-// TODO: implement Drop trait
 pub struct Thread<'a> {
     pub stack_pointer: IRQLock<*const u64>,
-    pub parent: Option<Arc<Thread<'a>>>,
+    /// Block of pages the kernel stack lives in. The boot thread runs on the stack from start.s
+    /// instead.
+    pub kernel_stack: Option<usize>,
+    /// Weak so that a parent and its children do not keep each other alive
+    pub parent: Option<Weak<Thread<'a>>>,
     pub status: IRQLock<ThreadStatus>,
+    pub priority: IRQLock<Priority>,
     pub name: String,
     pub id: u64,
     pub children: IRQLock<Vec<Arc<Thread<'a>>>>,
-    pub objects: IRQLock<Vec<(ObjectHandle, Box<dyn KernelObject>)>>, // TODO: find a more efficient way of doing this
+    /// Objects are shared by all handles that were duplicated or inherited from the same handle
+    pub objects: IRQLock<Vec<(ObjectHandle, Arc<dyn KernelObject>)>>, // TODO: find a more efficient way of doing this
     pub kernel_table: IRQLock<PageTable>,
     pub user_table: IRQLock<PageTable>,
+    /// Heap of the user program, empty until a program is loaded
+    pub heap: IRQLock<UserHeap>,
+    pub stats: IRQLock<ThreadStats>,
+    /// Exit code of a kill that waits for the thread to enter the kernel, because it was running
+    /// on another core when it was killed
+    pub pending_kill: IRQLock<Option<u64>>,
 }
 
 impl<'a> Thread<'a> {
     pub fn from_current() -> Self {
         Self {
             stack_pointer: IRQLock::new(0x0 as *const u64),
+            kernel_stack: None,
             parent: None,
             status: IRQLock::new(ThreadStatus::Running),
+            // Only runs when nothing else is ready, or when aging promotes it
+            priority: IRQLock::new(LOWEST_PRIORITY),
             name: String::from("Idle"),
             id: 0,
             children: IRQLock::new(vec![]),
             objects: IRQLock::new(vec![]),
             kernel_table: IRQLock::new(PageTable::from(mmu::get_kernel_table())),
             user_table: IRQLock::new(PageTable::from(mmu::get_user_table())),
+            heap: IRQLock::new(UserHeap::default()),
+            stats: IRQLock::new(ThreadStats::default()),
+            pending_kill: IRQLock::new(None),
         }
     }
 
-    pub fn return_to(&self) -> ! {
-        unsafe {
-            let user_table = self.user_table.lock().get_ttbr();
+    /// Restores the thread's saved frame and returns from the exception into it. The kernel lock
+    /// is released through its lock word once the core has left the old stack, before that
+    /// another core could pick up the thread that owns it.
+    pub fn return_to(&self, kernel_lock: &AtomicUsize) -> ! {
+        let user_table = self.user_table.lock().get_ttbr();
+        let stack_pointer = *self.stack_pointer.lock();
 
+        unsafe {
             // See the Armv8-A address translation manual
             asm!("msr ttbr0_el1, {ttbr0}", ttbr0 = in(reg) user_table);
             asm!("dsb ishst");
             //asm!("tlbi alle1");
             asm!("dsb ish", "isb");
 
-            asm!(
-                "mov sp, {sp}", sp = in(reg) *self.stack_pointer.lock()
-            );
-
             asm!(
                 "
+                mov sp, {sp}
+                stlr xzr, [{lock}]
+
                 ldp x0, x1, [sp, 0x100]
                 msr elr_el1, x0
                 msr spsr_el1, x1
@@ -119,11 +240,38 @@ impl<'a> Thread<'a> {
                 add sp, sp, 0x360
                 ldr lr, [sp], #16
                 eret
-                "
+                ",
+                sp = in(reg) stack_pointer,
+                lock = in(reg) kernel_lock.as_ptr(),
+                options(noreturn)
             );
         }
+    }
 
-        loop {}
+    pub fn priority(&self) -> Priority {
+        *self.priority.lock()
+    }
+
+    /// The frame that was saved when the thread last entered the kernel
+    pub fn saved_frame(&self) -> &InterruptFrame {
+        unsafe { &*(*self.stack_pointer.lock() as *const InterruptFrame) }
+    }
+
+    /// Whether the syscall the thread is making may access the memory range. A thread that entered
+    /// the kernel from EL0 is limited to the mapped pages of its user table, which must be writable
+    /// if the kernel writes to the range. Kernel threads may pass any address.
+    pub fn can_access(&self, address: u64, length: u64, write: bool) -> bool {
+        if !self.saved_frame().is_from_user() {
+            return true;
+        }
+
+        match address.checked_add(length) {
+            Some(end) if end <= USER_ADDRESS_LIMIT => self
+                .user_table
+                .lock()
+                .is_range_accessible(address, length, write),
+            _ => false,
+        }
     }
 
     /// Unsafe if the stack pointer is not accurate
@@ -135,147 +283,335 @@ impl<'a> Thread<'a> {
         }
     }
 
-    pub fn exec(&self, program: &str) {
-        let handle = cpu::open_object(program);
+    /// Rewinds the saved frame to the svc instruction, so that the syscall is made again when the
+    /// thread returns. The argument registers still hold the arguments, as nothing was returned.
+    fn restart_syscall(&self) {
+        unsafe {
+            let frame = &mut *(*self.stack_pointer.lock() as *mut InterruptFrame);
+            frame.elr -= 4;
+        }
+    }
+
+    pub fn exec(&self, program: &FileObject, arguments: &ProgramArguments) {
+        let loader = ElfLoader::new(program).expect("Error parsing elf");
 
-        let mut buffer: [u8; 840] = [b'\0'; 840];
+        let entry = self
+            .load_program(&loader, arguments)
+            .expect("Error loading elf");
 
-        let bytes_read = cpu::read_object(handle, &mut buffer);
+        let spsr_el1: u64 = 0;
 
-        let header = ELF64Header::try_from(&buffer[0..bytes_read]).expect("Error parsing elf");
+        unsafe {
+            asm!(
+                "msr spsr_el1, {spsr}",
+                "msr elr_el1, {elr}",
+                "msr sp_el0, {sp}",
+                "eret",
+                spsr = in(reg) spsr_el1,
+                elr = in(reg) entry.entry_address,
+                sp = in(reg) entry.stack_pointer,
+                in("x0") entry.argc,
+                in("x1") entry.argv,
+                in("x2") entry.envp,
+            );
+        }
+    }
 
-        let entry_address = header.program_entry_address;
+    /// Replaces the thread's user image with the program of the loader and sets up the initial
+    /// stack with the arguments.
+    pub fn load_program(
+        &self,
+        loader: &ElfLoader,
+        arguments: &ProgramArguments,
+    ) -> Result<ProgramEntry, ExecError> {
+        loader.validate_arguments(arguments)?;
 
-        let mut pheaders = vec![];
+        let mut user_table = self.user_table.lock();
 
-        let pheader_start = header.program_header_offset;
+        user_table.unmap_all();
 
-        for i in 0..header.program_header_number {
-            let pheader_offset = pheader_start + ((header.program_header_entry_size * i) as u64);
+        loader.load(&mut user_table)?;
 
-            let phdr = unsafe {
-                let buffer_offest = buffer.as_ptr().offset(pheader_offset as isize);
+        *self.heap.lock() = UserHeap::behind_image(loader.image_end());
 
-                *(buffer_offest as *const ProgramHeader)
-            };
+        let stack_page = PLATFORM.allocate_pages(0).ok_or(ExecError::OutOfMemory)?;
 
-            pheaders.push(phdr);
+        unsafe {
+            (*stack_page.page).fill(0);
         }
 
-        for pheader in pheaders
-            .iter()
-            .filter(|header| header.program_type == ProgramType::Loadable)
-        {
-            let vaddr = pheader.virtual_address;
-            let offset = pheader.offset;
+        // TODO: how to choose base stack pointer
+        if !user_table.map_user_address(
+            USER_STACK_PAGE,
+            stack_page.page as u64,
+            PagePermissions::READ_WRITE,
+        ) {
+            PLATFORM.free_page(stack_page.page as usize);
+            return Err(ExecError::OutOfMemory);
+        }
 
-            let file_size = pheader.file_size;
-            let memory_size = pheader.memory_size;
+        // Mappings of shared segment pages may have changed permissions
+        mmu::invalidate_tlb();
 
-            let start_page = vaddr & !(0xFFF);
-            let end_page = (vaddr + memory_size) & !(0xFFF);
+        Ok(loader.set_up_stack(unsafe { &mut *stack_page.page }, arguments))
+    }
 
-            let number_of_pages = (start_page - end_page) / (PAGE_SIZE as u64) + 1;
+    /// Rewrites the saved frame so that returning from the current exception enters a freshly
+    /// loaded user program at EL0
+    pub fn enter_user_on_return(&self, entry: &ProgramEntry) {
+        unsafe {
+            let frame = &mut *(*self.stack_pointer.lock() as *mut InterruptFrame);
 
-            // Page we are currently reading
-            let mut current_page = start_page;
+            frame.regs = [0; 32];
+            frame.fp_regs = [0; 32];
+            frame.regs[0] = entry.argc;
+            frame.regs[1] = entry.argv;
+            frame.regs[2] = entry.envp;
+            frame.elr = entry.entry_address;
+            frame.spsr = 0; // EL0t
 
-            // Offset in the read buffer
-            let mut buffer_offset = offset;
+            asm!("msr sp_el0, {}", in (reg) entry.stack_pointer);
+        }
+    }
+}
 
-            // Address of page we are writing to
-            let mut virtual_address = current_page;
+/// Returns the pages of a thread to the page allocator, its kernel objects are dropped with it.
+/// Threads are only dropped once their exit status has been collected, and never while the kernel
+/// is still running on their stack, see `Scheduler::exited_threads`.
+impl<'a> Drop for Thread<'a> {
+    fn drop(&mut self) {
+        // The boot thread uses the tables from the linker script, which are not ours to free
+        if let Some(kernel_stack) = self.kernel_stack {
+            self.user_table.lock().free();
 
-            // Bytes left to read
-            let mut to_read = file_size;
+            PLATFORM.free_page(kernel_stack);
+        }
+    }
+}
 
-            let mut offset_in_page = vaddr & 0xFFF;
+/// The scheduling state of one core
+pub struct CoreScheduler<'a> {
+    pub current_thread: Arc<Thread<'a>>,
+    /// Runs when there is nothing else to do. It is never queued, so no other core can pick it.
+    pub idle_thread: Arc<Thread<'a>>,
+    pub thread_queue: RunQueue<'a>,
+    /// Whether the core started a new time slice since its timers were last programmed
+    pub slice_started: bool,
+    /// System time up to which the time of the core has been charged to its threads
+    pub accounted_until: u64,
+}
 
-            for _ in 0..number_of_pages {
-                if !self.user_table.lock().is_addr_mapped(virtual_address) {
-                    let page = PLATFORM.allocate_zeroed_page();
+pub struct Scheduler<'a> {
+    /// Indexed by core id, cores are added as they come up
+    pub cores: [Option<CoreScheduler<'a>>; CORES],
+    pub threads: Vec<Arc<Thread<'a>>>,
+    pub waiting_threads: Vec<Arc<Thread<'a>>>,
+    pub wait_queues: WaitQueues<'a>,
+    /// Threads that exited since the last entry into the kernel. The exit path runs on the kernel
+    /// stack of the dying thread, so they are kept alive until another thread enters the kernel.
+    pub exited_threads: Vec<Arc<Thread<'a>>>,
+}
 
-                    self.user_table
-                        .lock()
-                        .map_user_address(virtual_address, page.page as u64);
-                    // TODO: data and istruction buffer?
+impl<'a> Scheduler<'a> {
+    pub fn new() -> Self {
+        let mut scheduler = Self {
+            cores: core::array::from_fn(|_| None),
+            threads: vec![],
+            waiting_threads: vec![],
+            wait_queues: WaitQueues::new(),
+            exited_threads: vec![],
+        };
 
-                    // TODO currect buffering?
-                    let read_length = core::cmp::min(to_read, PAGE_SIZE as u64 - offset_in_page);
+        scheduler.add_core();
 
-                    for i in offset_in_page..offset_in_page + read_length {
-                        unsafe {
-                            (*(page.page as *mut [u8; PAGE_SIZE]))[i as usize] =
-                                buffer[buffer_offset as usize];
-                        }
+        scheduler
+    }
 
-                        buffer_offset += 1
-                    }
+    /// Registers the calling core. The code it is running becomes the core's idle thread.
+    pub fn add_core(&mut self) {
+        let idle_thread = Arc::new(Thread::from_current());
 
-                    to_read -= read_length;
+        self.threads.push(Arc::clone(&idle_thread));
 
-                    virtual_address += PAGE_SIZE as u64;
-                    current_page += PAGE_SIZE as u64;
+        self.cores[cpu::core_id()] = Some(CoreScheduler {
+            current_thread: Arc::clone(&idle_thread),
+            idle_thread,
+            thread_queue: RunQueue::new(),
+            slice_started: false,
+            accounted_until: PLATFORM.get_timer().get_micros(),
+        });
+    }
 
-                    offset_in_page = 0;
-                }
-            }
+    fn core(&self) -> &CoreScheduler<'a> {
+        self.cores[cpu::core_id()]
+            .as_ref()
+            .expect("Core has not been added to the scheduler")
+    }
+
+    fn core_mut(&mut self) -> &mut CoreScheduler<'a> {
+        self.cores[cpu::core_id()]
+            .as_mut()
+            .expect("Core has not been added to the scheduler")
+    }
+
+    /// The thread running on the calling core
+    pub fn current_thread(&self) -> &Arc<Thread<'a>> {
+        &self.core().current_thread
+    }
+
+    fn is_idle_thread(&self, thread: &Arc<Thread<'a>>) -> bool {
+        self.cores
+            .iter()
+            .flatten()
+            .any(|core| Arc::ptr_eq(&core.idle_thread, thread))
+    }
+
+    /// Queues a thread that became ready on the core with the least work. An idle core is woken
+    /// up, as it is waiting for an interrupt with its timer stopped.
+    fn make_ready(&mut self, thread: Arc<Thread<'a>>) {
+        *thread.status.lock() = ThreadStatus::Ready;
+        thread.stats.lock().ready_since = Some(PLATFORM.get_timer().get_micros());
+
+        // Idle threads run whenever their core has nothing else to do
+        if self.is_idle_thread(&thread) {
+            return;
         }
 
-        // Could we do this earlier?
-        //cpu::close_object(handle);
+        let (core_id, core) = self
+            .cores
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(core_id, core)| Some((core_id, core.as_mut()?)))
+            .min_by_key(|(_, core)| {
+                let busy = !Arc::ptr_eq(&core.current_thread, &core.idle_thread);
 
-        let spsr_el1 = 0;
+                core.thread_queue.len() + busy as usize
+            })
+            .expect("No cores to schedule on");
 
-        let stack_page = PLATFORM.allocate_zeroed_page();
-        let sp = (0x80_000 /*(stack_page.page as usize)*/ + PAGE_SIZE - 8) & (0xFFFF_FFFF_FFFF);
+        core.thread_queue.push_back(thread);
 
-        // TODO: how to choose base stack pointer
-        self.user_table
-            .lock()
-            .map_user_address(0x80_000, stack_page.page as u64);
+        if Arc::ptr_eq(&core.current_thread, &core.idle_thread) {
+            PLATFORM.wake_core(core_id);
+        }
+    }
 
-        unsafe {
-            asm!("dsb ishst");
-            //asm!("tlbi alle1");
-            asm!("dsb ish", "isb");
+    /// Puts the current thread back on the calling core's queue
+    fn requeue_current(&mut self) {
+        let core = self.core_mut();
+        let thread = Arc::clone(&core.current_thread);
+
+        *thread.status.lock() = ThreadStatus::Ready;
+
+        if !Arc::ptr_eq(&thread, &core.idle_thread) {
+            core.thread_queue.push_back(thread);
         }
+    }
 
-        unsafe {
-            asm!("msr spsr_el1, {0:x}", in (reg) spsr_el1);
-            asm!("msr elr_el1, {0:x}", in (reg) entry_address);
-            asm!("msr sp_el0, {}", in (reg) sp);
-            asm!("eret");
+    /// Takes a thread from the calling core's queue, or from the busiest core if it has none
+    fn next_thread(&mut self) -> Arc<Thread<'a>> {
+        if let Some(thread) = self.core_mut().thread_queue.pop_front() {
+            return thread;
         }
+
+        let stolen = self
+            .cores
+            .iter_mut()
+            .flatten()
+            .max_by_key(|core| core.thread_queue.len())
+            .and_then(|core| core.thread_queue.pop_front());
+
+        stolen.unwrap_or_else(|| Arc::clone(&self.core().idle_thread))
     }
-}
 
-pub struct Scheduler<'a> {
-    pub current_thread: Arc<Thread<'a>>,
-    pub threads: Vec<Arc<Thread<'a>>>,
-    pub thread_queue: VecDeque<Arc<Thread<'a>>>,
-    pub waiting_threads: Vec<Arc<Thread<'a>>>,
-}
+    /// Makes the next thread the current thread of the calling core
+    fn switch_to_next(&mut self) {
+        let new_thread = self.next_thread();
 
-impl<'a> Scheduler<'a> {
-    pub fn new() -> Self {
-        let current_thread = Arc::new(Thread::from_current());
+        *new_thread.status.lock() = ThreadStatus::Running;
 
-        Self {
-            current_thread: current_thread.clone(),
-            threads: vec![current_thread],
-            thread_queue: VecDeque::new(),
-            waiting_threads: vec![],
+        // The time in the kernel so far belongs to the thread that is switched away from
+        let now = self.account_time(false);
+
+        if !Arc::ptr_eq(&new_thread, self.current_thread()) {
+            let mut stats = new_thread.stats.lock();
+
+            stats.context_switches += 1;
+
+            if let Some(ready_since) = stats.ready_since.take() {
+                let latency = now - ready_since;
+
+                stats.wake_ups += 1;
+                stats.total_wake_up_latency += latency;
+                stats.max_wake_up_latency = stats.max_wake_up_latency.max(latency);
+            }
+        }
+
+        let core = self.core_mut();
+
+        core.current_thread = new_thread;
+        core.slice_started = true;
+    }
+
+    /// Returns whether the calling core runs a thread other than its idle thread, if it started a
+    /// new time slice since the last call
+    pub fn take_slice_start(&mut self) -> Option<bool> {
+        let core = self.core_mut();
+
+        if !core.slice_started {
+            return None;
+        }
+
+        core.slice_started = false;
+
+        Some(!Arc::ptr_eq(&core.current_thread, &core.idle_thread))
+    }
+
+    /// Charges the time since the last call to the current thread of the calling core, as user
+    /// time when the core is entering the kernel and as kernel time when it leaves or switches
+    /// threads. Returns the current time.
+    pub fn account_time(&mut self, entering_kernel: bool) -> u64 {
+        let now = PLATFORM.get_timer().get_micros();
+        let core = self.core_mut();
+        let elapsed = now.saturating_sub(core.accounted_until);
+        let mut stats = core.current_thread.stats.lock();
+
+        if entering_kernel {
+            stats.user_time += elapsed;
+        } else {
+            stats.kernel_time += elapsed;
+        }
+
+        drop(stats);
+        core.accounted_until = now;
+
+        now
+    }
+
+    /// Writes a snapshot of as many threads as fit into the buffer and returns the number of
+    /// threads, so that the caller can retry with a larger buffer
+    pub fn list_threads(&mut self, buffer: &mut [MaybeUninit<ThreadInfo>]) {
+        for (entry, thread) in buffer.iter_mut().zip(self.threads.iter()) {
+            entry.write(ThreadInfo::from_thread(thread));
         }
+
+        self.set_current_thread_return(self.threads.len() as u64);
+    }
+
+    /// Releases the scheduler's references to exited threads. Must not be called from the exit
+    /// path itself, which is still running on the stack of the exited thread.
+    pub fn reap_exited_threads(&mut self) {
+        self.exited_threads.clear();
     }
 
     pub fn add_thread(&mut self, thread: Thread<'a>) {
         let thread = Arc::new(thread);
-        self.thread_queue.push_back(Arc::clone(&thread));
 
-        self.current_thread.children.lock().push(thread.clone());
+        self.current_thread().children.lock().push(thread.clone());
+
+        self.threads.push(Arc::clone(&thread));
 
-        self.threads.push(thread);
+        self.make_ready(thread);
 
         /*for thread in &self.threads {
             crate::println!(
@@ -293,89 +629,70 @@ impl<'a> Scheduler<'a> {
     pub fn update_waits(&mut self) {
         let time = PLATFORM.get_timer().get_micros();
 
-        for thread in self.threads.iter_mut() {
-            if let ThreadStatus::Waiting(timeout) = *thread.status.lock() {
-                if timeout < time {
-                    *thread.status.lock() = ThreadStatus::Ready;
-                    self.thread_queue.push_back(Arc::clone(thread));
-                }
-            }
+        let woken: Vec<Arc<Thread<'a>>> = self
+            .threads
+            .iter()
+            .filter(|thread| {
+                matches!(*thread.status.lock(), ThreadStatus::Waiting(timeout) if timeout < time)
+            })
+            .cloned()
+            .collect();
+
+        for thread in woken {
+            self.make_ready(thread);
         }
     }
 
     pub fn update_current(&mut self, frame: &InterruptFrame) {
-        *self.current_thread.stack_pointer.lock() = frame as *const InterruptFrame as *const u64;
+        *self.current_thread().stack_pointer.lock() = frame as *const InterruptFrame as *const u64;
     }
 
     pub fn choose_thread(&mut self) -> Arc<Thread<'a>> {
-        *self.current_thread.status.lock() = ThreadStatus::Ready;
-
-        self.thread_queue
-            .push_back(Arc::clone(&self.current_thread));
+        self.requeue_current();
+        self.switch_to_next();
 
-        let new_thread = self
-            .thread_queue
-            .pop_front()
-            .expect("Unable to find thread to schedule");
-
-        self.current_thread = Arc::clone(&new_thread);
-
-        *new_thread.status.lock() = ThreadStatus::Running;
-
-        return Arc::clone(&new_thread);
-    }
-
-    pub fn return_to_current(&self) {
-        self.current_thread.return_to();
+        Arc::clone(self.current_thread())
     }
 
     pub fn set_current_stack_pointer(&mut self, pointer: *const u64) {
-        *self.current_thread.stack_pointer.lock() = pointer;
+        *self.current_thread().stack_pointer.lock() = pointer;
     }
 
     pub fn schedule(&mut self) {
-        /*crate::println!("\n\n\nScheduling \n\n");
-        crate::println!("Threads:");
-        for thread in &self.threads {
-            crate::println!(
-                "{} (Strong count {}) @ {:#?}: {:?}",
-                &thread.name,
-                Rc::strong_count(thread),
-                Rc::as_ptr(thread),
-                *thread.status.lock()
-            );
-        }
+        self.requeue_current();
+        self.switch_to_next();
+    }
 
-        crate::println!("Queue:");
-        for thread in &self.thread_queue {
-            crate::println!(
-                "{} (Strong count {}) @ {:#?}: {:?}",
-                &thread.name,
-                Rc::strong_count(thread),
-                Rc::as_ptr(thread),
-                *thread.status.lock()
-            );
-        }
-        crate::println!(
-            "Cloning! {:?}. With strong count {}",
-            &self.current_thread,
-            Rc::strong_count(&self.current_thread)
-        );*/
-        let former_thread = Arc::clone(&self.current_thread);
-        //crate::println!("Cloned!");
-        *former_thread.status.lock() = ThreadStatus::Ready;
-        self.thread_queue.push_back(former_thread);
+    pub fn exit_current_thread(&mut self, code: u64) {
+        let dying_thread = Arc::clone(self.current_thread());
 
-        //crate::println!("Old pushed");
+        self.terminate(dying_thread, code);
 
-        let new_thread = self.thread_queue.pop_front().expect("No threads on queue");
-        *new_thread.status.lock() = ThreadStatus::Running;
-        self.current_thread = new_thread;
+        self.switch_to_next();
     }
 
-    pub fn exit_current_thread(&mut self, code: u64) {
-        let dying_thread = Arc::clone(&self.current_thread);
-        *dying_thread.status.lock() = ThreadStatus::Exited(code);
+    /// Ends a thread that is not running on another core. It is taken off the queues, its joiners
+    /// are woken with the exit code and it is kept until the exit status has been collected.
+    fn terminate(&mut self, dying_thread: Arc<Thread<'a>>, code: u64) {
+        let previous_status =
+            core::mem::replace(&mut *dying_thread.status.lock(), ThreadStatus::Exited(code));
+
+        match previous_status {
+            ThreadStatus::Ready => {
+                for core in self.cores.iter_mut().flatten() {
+                    if core.thread_queue.remove(&dying_thread) {
+                        break;
+                    }
+                }
+            }
+            ThreadStatus::Waiting(_) => self
+                .waiting_threads
+                .retain(|thread| !Arc::ptr_eq(thread, &dying_thread)),
+            ThreadStatus::Blocked(queue) => {
+                self.wait_queues.remove(queue, &dying_thread);
+            }
+            _ => {}
+        }
 
         let dying_thread_index = self
             .threads
@@ -384,43 +701,100 @@ impl<'a> Scheduler<'a> {
             .expect("Dying thread is not listed as a thread.");
         self.threads.remove(dying_thread_index);
 
-        let dying_thread_id = dying_thread.id;
+        let joiners = self
+            .wait_queues
+            .wake(WaitQueueID::Join(dying_thread.id), usize::MAX);
+
+        for thread in joiners {
+            thread.set_return_value(code);
+
+            // Only parents can join, so the exit status has now been collected
+            thread
+                .children
+                .lock()
+                .retain(|child| !Arc::ptr_eq(child, &dying_thread));
 
-        self.threads.iter().for_each(|thread| {
-            if let ThreadStatus::Joining(id) = *thread.status.lock() {
-                if id == dying_thread_id {
-                    unsafe {
-                        let frame = &mut *(*thread.stack_pointer.lock() as *mut InterruptFrame);
+            self.make_ready(thread);
+        }
 
-                        frame.regs[0] = code;
+        // Other threads may be waiting for the ends of channels to close
+        let objects = core::mem::take(&mut *dying_thread.objects.lock());
 
-                        self.thread_queue.push_back(thread.clone());
-                    }
-                }
+        for (_, object) in objects {
+            self.close_object(object);
+        }
+
+        self.exited_threads.push(dying_thread);
+    }
+
+    /// Ends a descendant of the current thread with the given exit code, returning 0 or
+    /// [KILL_ERROR]. The descendants of the killed thread are killed with the same code, so that
+    /// no threads are left behind that nobody can join. A thread that is running on another core
+    /// ends when that core next enters the kernel, which it is interrupted to do.
+    pub fn kill_thread(&mut self, thread_id: ThreadID, code: u64) {
+        let current_thread = Arc::clone(self.current_thread());
+
+        let target = self
+            .threads
+            .iter()
+            .find(|thread| thread.id == thread_id)
+            .filter(|thread| is_ancestor(&current_thread, thread))
+            .cloned();
+
+        match target {
+            Some(target) => {
+                self.set_current_thread_return(0);
+                self.kill_tree(target, code);
             }
+            None => self.set_current_thread_return(KILL_ERROR),
+        }
+    }
+
+    fn kill_tree(&mut self, thread: Arc<Thread<'a>>, code: u64) {
+        let children = thread.children.lock().clone();
+
+        let running_on = self.cores.iter().position(|core| {
+            core.as_ref()
+                .is_some_and(|core| Arc::ptr_eq(&core.current_thread, &thread))
         });
 
-        /*if let Some(ref parent) = dying_thread.parent {
-            let children = &mut parent.children.lock();
+        match running_on {
+            Some(core_id) => {
+                *thread.pending_kill.lock() = Some(code);
+                PLATFORM.wake_core(core_id);
+            }
+            None => self.terminate(thread, code),
+        }
 
-            children.retain(|child| child.id == dying_thread_id);
-        }*/
+        for child in children {
+            if !matches!(*child.status.lock(), ThreadStatus::Exited(_)) {
+                self.kill_tree(child, code);
+            }
+        }
+    }
 
-        self.current_thread = self.thread_queue.pop_front().expect("No threads on queue");
+    /// Ends the current thread if it was killed while it was running, see [Self::kill_thread].
+    /// Returns whether it did.
+    pub fn exit_if_killed(&mut self) -> bool {
+        let pending_kill = self.current_thread().pending_kill.lock().take();
+
+        match pending_kill {
+            Some(code) => {
+                self.exit_current_thread(code);
+                true
+            }
+            None => false,
+        }
     }
 
     pub fn delay_current_thread(&mut self, delay: u64) {
-        let thread_to_delay = Arc::clone(&self.current_thread);
+        let thread_to_delay = Arc::clone(self.current_thread());
 
         *thread_to_delay.status.lock() = ThreadStatus::Waiting(delay);
 
         self.waiting_threads.push(thread_to_delay);
 
-        let new_thread = self.thread_queue.pop_front().expect("No threads on queue");
-
-        *new_thread.status.lock() = ThreadStatus::Running;
-
-        self.current_thread = new_thread;
+        self.switch_to_next();
     }
 
     pub fn get_next_thread_wakeup(&self) -> Option<u64> {
@@ -435,6 +809,7 @@ impl<'a> Scheduler<'a> {
 
     pub fn wake_sleeping(&mut self) {
         let current_time = PLATFORM.get_timer().get_micros();
+        let mut woken = vec![];
 
         self.waiting_threads.retain(|thread| {
             let wake_time = match *thread.status.lock() {
@@ -443,110 +818,352 @@ impl<'a> Scheduler<'a> {
             };
 
             if wake_time <= current_time {
-                *thread.status.lock() = ThreadStatus::Ready;
-
-                self.thread_queue.push_back(Arc::clone(&thread));
+                woken.push(Arc::clone(thread));
 
                 return false;
             } else {
                 return true;
             }
-        })
+        });
+
+        for thread in woken {
+            self.make_ready(thread);
+        }
     }
 
     pub fn set_current_thread_return(&mut self, value: u64) {
-        let frame = *self.current_thread.stack_pointer.lock() as *mut InterruptFrame;
-
-        unsafe {
-            let frame = &mut *frame;
-            frame.regs[0] = value;
-        }
+        self.current_thread().set_return_value(value);
     }
 
     pub fn join_current_thread(&mut self, thread_id: ThreadID) {
-        let child_thread_index = self
-            .current_thread
+        let current_thread = Arc::clone(self.current_thread());
+
+        let child_thread_index = current_thread
             .children
             .lock()
             .iter()
             .position(|child| child.id == thread_id)
             .expect(alloc::format!("Waiting on child that doesn't exist {}", thread_id).as_str());
 
-        let child_thread_status = *self.current_thread.children.lock()[child_thread_index]
+        let child_thread_status = *current_thread.children.lock()[child_thread_index]
             .status
             .lock();
 
         if let ThreadStatus::Exited(exit_code) = child_thread_status {
-            self.current_thread
-                .children
-                .lock()
-                .remove(child_thread_index);
+            current_thread.children.lock().remove(child_thread_index);
 
-            self.current_thread.set_return_value(exit_code);
+            current_thread.set_return_value(exit_code);
         } else {
-            self.current_thread.status.lock();
-            *self.current_thread.status.lock() = ThreadStatus::Joining(thread_id);
+            self.block_current_thread(WaitQueueID::Join(thread_id));
+        }
+    }
+
+    /// Takes the current thread off the CPU until the wait queue is woken
+    fn block_current_thread(&mut self, queue: WaitQueueID) {
+        let current_thread = Arc::clone(self.current_thread());
+
+        *current_thread.status.lock() = ThreadStatus::Blocked(queue);
+
+        self.wait_queues.push(queue, current_thread);
 
-            self.current_thread = self.thread_queue.pop_front().expect("No threads on queue");
+        self.switch_to_next();
+    }
+
+    /// The wait queue of a futex word as seen by the current thread, or None if the address is
+    /// not a valid word. Threads from EL0 may only use words mapped in their user table.
+    fn futex_queue(&self, address: usize) -> Option<WaitQueueID> {
+        if address == 0
+            || !address.is_multiple_of(core::mem::align_of::<AtomicU64>())
+            || !self.current_thread().can_access(
+                address as u64,
+                core::mem::size_of::<AtomicU64>() as u64,
+                false,
+            )
+        {
+            return None;
         }
+
+        let table = if address as u64 >= mmu::KERNEL_ADDRESS_START {
+            0
+        } else {
+            self.current_thread().user_table.lock().get_ttbr()
+        };
+
+        Some(WaitQueueID::Futex { table, address })
     }
 
-    pub fn yield_current_thread(&mut self) {
-        let yielding_thread = self.current_thread.clone();
+    /// Blocks the current thread on the futex word if it holds the expected value. Wakers take
+    /// the kernel lock, so a wake up after the value changed cannot be missed. Returns
+    /// [FUTEX_WOKEN] once woken, [FUTEX_MISMATCH] or [FUTEX_ERROR].
+    pub fn futex_wait(&mut self, address: usize, expected: u64) {
+        let queue = match self.futex_queue(address) {
+            Some(queue) => queue,
+            None => return self.set_current_thread_return(FUTEX_ERROR),
+        };
 
-        self.thread_queue.push_back(yielding_thread);
+        let value = unsafe { (*(address as *const AtomicU64)).load(Ordering::SeqCst) };
+
+        if value != expected {
+            return self.set_current_thread_return(FUTEX_MISMATCH);
+        }
 
-        self.current_thread = self.thread_queue.pop_front().expect("No threads on queue");
+        self.set_current_thread_return(FUTEX_WOKEN);
+        self.block_current_thread(queue);
     }
 
-    pub fn add_object_to_current_thread(&self, object: Box<dyn KernelObject>, id: ObjectHandle) {
-        self.current_thread.objects.lock().push((id, object));
+    /// Wakes up to count threads waiting on the futex word and returns how many were woken
+    pub fn futex_wake(&mut self, address: usize, count: usize) {
+        let queue = match self.futex_queue(address) {
+            Some(queue) => queue,
+            None => return self.set_current_thread_return(FUTEX_ERROR),
+        };
+
+        let woken = self.wake_queue(queue, count, None);
+
+        self.set_current_thread_return(woken as u64);
     }
 
-    pub fn remove_object_from_current_thread(&self, handle: ObjectHandle) {
-        // TODO: error handling?
-        // TODO: will this call drop?
+    /// Looks up the current thread or one of its children. Id 0 always refers to the current
+    /// thread, as the idle threads are nobody's children.
+    fn find_own_thread(&self, thread_id: ThreadID) -> Option<Arc<Thread<'a>>> {
+        let current_thread = self.current_thread();
 
-        self.current_thread
-            .objects
+        if thread_id == 0 || thread_id == current_thread.id {
+            return Some(Arc::clone(current_thread));
+        }
+
+        current_thread
+            .children
             .lock()
-            .retain(|(id, _)| *id != handle);
+            .iter()
+            .find(|child| child.id == thread_id)
+            .cloned()
     }
 
-    pub fn read(&mut self, handle: ObjectHandle, buffer: &mut [u8]) {
-        let mut return_value = 0;
+    pub fn get_priority(&mut self, thread_id: ThreadID) {
+        let priority = self
+            .find_own_thread(thread_id)
+            .map_or(PRIORITY_ERROR, |thread| thread.priority() as u64);
+
+        self.set_current_thread_return(priority);
+    }
+
+    /// Changes the priority of the current thread or a child and returns the previous priority.
+    /// The current thread is preempted if a thread queued on its core now outranks it.
+    pub fn set_priority(&mut self, thread_id: ThreadID, priority: u64) {
+        let thread = match self.find_own_thread(thread_id) {
+            Some(thread) if priority <= HIGHEST_PRIORITY as u64 => thread,
+            _ => {
+                self.set_current_thread_return(PRIORITY_ERROR);
+                return;
+            }
+        };
+
+        let previous = core::mem::replace(&mut *thread.priority.lock(), priority as Priority);
+
+        // Queued threads move to the level of their new priority
+        for core in self.cores.iter_mut().flatten() {
+            if core.thread_queue.remove(&thread) {
+                core.thread_queue.push_back(thread);
+                break;
+            }
+        }
+
+        self.set_current_thread_return(previous as u64);
 
+        let core = self.core();
+
+        if core
+            .thread_queue
+            .highest_priority()
+            .is_some_and(|highest| highest > core.current_thread.priority())
         {
-            let objects = self.current_thread.objects.lock();
+            self.schedule();
+        }
+    }
 
-            for i in 0..objects.len() {
-                let (id, o) = &objects[i];
+    pub fn yield_current_thread(&mut self) {
+        self.requeue_current();
+        self.switch_to_next();
+    }
 
-                if *id == handle {
-                    return_value = o.read(buffer);
-                    break;
-                }
+    pub fn add_object_to_current_thread(&self, object: Arc<dyn KernelObject>, id: ObjectHandle) {
+        self.current_thread().objects.lock().push((id, object));
+    }
+
+    pub fn remove_object_from_current_thread(&mut self, handle: ObjectHandle) {
+        // TODO: error handling?
+        let object = {
+            let mut objects = self.current_thread().objects.lock();
+
+            objects
+                .iter()
+                .position(|(id, _)| *id == handle)
+                .map(|index| objects.remove(index).1)
+        };
+
+        if let Some(object) = object {
+            self.close_object(object);
+        }
+    }
+
+    /// Drops a handle to an object. If it was the last one, the object is closed and the threads
+    /// that were waiting for that are woken.
+    fn close_object(&mut self, object: Arc<dyn KernelObject>) {
+        // Other handles keep the object open
+        if Arc::strong_count(&object) > 1 {
+            return;
+        }
+
+        let queue = object.close();
+
+        drop(object);
+
+        if let Some(queue) = queue {
+            self.wake_queue(queue, usize::MAX, None);
+        }
+    }
+
+    /// Wakes up to count threads of the wait queue, setting their return value if one is given.
+    /// Returns the number of threads woken.
+    fn wake_queue(&mut self, queue: WaitQueueID, count: usize, return_value: Option<u64>) -> usize {
+        let woken = self.wait_queues.wake(queue, count);
+        let number_woken = woken.len();
+
+        for thread in woken {
+            if let Some(value) = return_value {
+                thread.set_return_value(value);
+            }
+
+            self.make_ready(thread);
+        }
+
+        number_woken
+    }
+
+    /// Adds another handle to the object of the handle and returns it, or 0 if the current thread
+    /// has no such handle
+    pub fn duplicate_object(&mut self, handle: ObjectHandle, new_handle: ObjectHandle) {
+        let object = self.find_object(handle);
+
+        match object {
+            Some(object) => {
+                self.add_object_to_current_thread(object, new_handle);
+                self.set_current_thread_return(new_handle);
             }
+            None => self.set_current_thread_return(0),
         }
+    }
 
-        self.set_current_thread_return(return_value as u64);
+    /// Moves the program break of the current thread and returns the new break, or the current one
+    /// if it cannot be moved
+    pub fn set_program_break(&mut self, address: u64) {
+        let thread = self.current_thread();
+        let program_break = thread
+            .heap
+            .lock()
+            .set_break(&mut thread.user_table.lock(), address);
+
+        self.set_current_thread_return(program_break);
+    }
+
+    /// Maps zeroed pages into the current thread and returns their address, or
+    /// [user_memory::MEMORY_ERROR]
+    pub fn map_anonymous(&mut self, address: u64, length: u64, protection: u64) {
+        let mapped = user_memory::map_anonymous(
+            &mut self.current_thread().user_table.lock(),
+            address,
+            length,
+            protection,
+        );
+
+        self.set_current_thread_return(mapped);
+    }
+
+    /// Unmaps pages of the current thread that were mapped with [Scheduler::map_anonymous]
+    pub fn unmap_anonymous(&mut self, address: u64, length: u64) {
+        let result = user_memory::unmap_anonymous(
+            &mut self.current_thread().user_table.lock(),
+            address,
+            length,
+        );
+
+        self.set_current_thread_return(result);
+    }
+
+    /// The handles of the current thread that a new thread inherits, see [InheritedHandles]
+    pub fn inherited_objects(
+        &self,
+        inherited: InheritedHandles,
+    ) -> Vec<(ObjectHandle, Arc<dyn KernelObject>)> {
+        let objects = self.current_thread().objects.lock();
+
+        match inherited {
+            InheritedHandles::None => vec![],
+            InheritedHandles::All => objects.clone(),
+            InheritedHandles::Only(handles) => objects
+                .iter()
+                .filter(|(id, _)| handles.contains(id))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn find_object(&self, handle: ObjectHandle) -> Option<Arc<dyn KernelObject>> {
+        self.current_thread()
+            .objects
+            .lock()
+            .iter()
+            .find(|(id, _)| *id == handle)
+            .map(|(_, object)| Arc::clone(object))
+    }
+
+    pub fn read(&mut self, handle: ObjectHandle, buffer: &mut [u8]) {
+        let transfer = self
+            .find_object(handle)
+            .map_or(Transfer::Done(0, None), |object| object.read(buffer));
+
+        self.complete_transfer(transfer);
     }
 
     pub fn write(&mut self, handle: ObjectHandle, buffer: &mut [u8]) {
-        let mut return_value = 0;
-        {
-            let objects = self.current_thread.objects.lock();
+        let transfer = self
+            .find_object(handle)
+            .map_or(Transfer::Done(0, None), |object| object.write(buffer));
 
-            for i in 0..objects.len() {
-                let (id, o) = &objects[i];
+        self.complete_transfer(transfer);
+    }
+
+    /// Returns the number of bytes transferred to the current thread, or blocks it so that it
+    /// makes the syscall again once woken
+    fn complete_transfer(&mut self, transfer: Transfer) {
+        match transfer {
+            Transfer::Done(bytes, wake) => {
+                self.set_current_thread_return(bytes as u64);
 
-                if *id == handle {
-                    return_value = o.write(buffer);
-                    break;
+                if let Some(queue) = wake {
+                    self.wake_queue(queue, usize::MAX, None);
                 }
             }
+            Transfer::Blocked(queue) => {
+                self.current_thread().restart_syscall();
+                self.block_current_thread(queue);
+            }
         }
+    }
+}
 
-        self.set_current_thread_return(return_value as u64);
+/// Whether the ancestor is the parent of the thread, or a parent of one of its parents
+fn is_ancestor<'a>(ancestor: &Arc<Thread<'a>>, thread: &Arc<Thread<'a>>) -> bool {
+    let mut parent = thread.parent.as_ref().and_then(Weak::upgrade);
+
+    while let Some(thread) = parent {
+        if Arc::ptr_eq(&thread, ancestor) {
+            return true;
+        }
+
+        parent = thread.parent.as_ref().and_then(Weak::upgrade);
     }
+
+    false
 }
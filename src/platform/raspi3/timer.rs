@@ -45,8 +45,10 @@ impl TimerRegisters {
         self.compare_values[3].set(self.counter_low_bits.get() + micros)
     }
 
-    pub fn set_kernel_timeout(&mut self, millis: u32) {
-        self.compare_values[1].set(self.counter_low_bits.get() + millis);
+    /// Raises the kernel timer interrupt at the given time in microseconds. Only the low 32 bits
+    /// of the counter are compared, so the time has to be less than 71 minutes away.
+    pub fn set_kernel_wake_up(&mut self, time: u64) {
+        self.compare_values[1].set(time as u32);
     }
 
     pub fn clear_matches(&mut self) {
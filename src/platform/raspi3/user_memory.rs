@@ -0,0 +1,212 @@
+//! Memory that user programs allocate while they run
+//!
+//! The heap starts at the page behind the program image and grows and shrinks as the program
+//! moves its break with brk. Anonymous mappings are made with mmap, at an address the program
+//! chooses or one the kernel picks, and removed with munmap. They are confined to the part of the
+//! address space from [MMAP_START], so that they cannot replace the image, the stack or the heap.
+//! New pages are always zeroed.
+
+use alloc::vec::Vec;
+
+use super::loader::USER_ADDRESS_LIMIT;
+use super::page_table::{PagePermissions, PageTable};
+use crate::aarch64::mmu::{self, KERNEL_ADDRESS_START};
+use crate::allocator::align;
+use crate::allocator::buddy_alloc::PAGE_ALLOCATOR;
+use crate::allocator::page_allocator::PAGE_SIZE;
+use crate::platform::platform_devices::PLATFORM;
+
+/// Returned by mmap and munmap for invalid ranges and when memory runs out
+pub const MEMORY_ERROR: u64 = u64::MAX;
+
+/// Start of the anonymous mappings, the heap can grow up to it
+pub const MMAP_START: u64 = 0x10_0000_0000;
+
+/// Protection flags of mmap, mapped pages can always be read
+pub const PROT_WRITE: u64 = 1 << 0;
+pub const PROT_EXEC: u64 = 1 << 1;
+
+/// The heap of a user program
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UserHeap {
+    /// Start of the heap, 0 for threads without a program image, which have no heap
+    start: u64,
+    /// End of the heap, pages are mapped up to the break rounded up to a page
+    program_break: u64,
+}
+
+impl UserHeap {
+    /// A heap that starts at the page behind the end of the program image
+    pub fn behind_image(image_end: u64) -> Self {
+        let start = align(image_end as usize, PAGE_SIZE) as u64;
+
+        Self {
+            start,
+            program_break: start,
+        }
+    }
+
+    /// Moves the program break to the address, mapping or unmapping the pages in between.
+    /// Returns the new break, or the old one if the address lies outside of the heap or the memory
+    /// runs out, so address 0 queries the break.
+    pub fn set_break(&mut self, table: &mut PageTable, address: u64) -> u64 {
+        if self.start == 0 || address < self.start || address > MMAP_START {
+            return self.program_break;
+        }
+
+        let mapped_end = page_align(self.program_break);
+        let new_end = page_align(address);
+
+        if new_end > mapped_end {
+            if !fits_in_memory(new_end - mapped_end)
+                || !map_zeroed_pages(table, mapped_end, new_end, PagePermissions::READ_WRITE)
+            {
+                return self.program_break;
+            }
+        } else {
+            unmap_pages(table, new_end, mapped_end);
+        }
+
+        self.program_break = address;
+
+        address
+    }
+}
+
+/// Maps length bytes of zeroed pages at the address, or at the lowest free range from
+/// [MMAP_START] if the address is 0. Returns the address of the mapping or [MEMORY_ERROR].
+pub fn map_anonymous(table: &mut PageTable, address: u64, length: u64, protection: u64) -> u64 {
+    if length == 0 || !fits_in_memory(length) || protection & !(PROT_WRITE | PROT_EXEC) != 0 {
+        return MEMORY_ERROR;
+    }
+
+    let length = page_align(length);
+
+    let start = if address == 0 {
+        match find_free_range(table, length) {
+            Some(start) => start,
+            None => return MEMORY_ERROR,
+        }
+    } else if is_mmap_range(address, length) {
+        address
+    } else {
+        return MEMORY_ERROR;
+    };
+
+    let permissions =
+        PagePermissions::new(protection & PROT_WRITE != 0, protection & PROT_EXEC != 0);
+
+    if map_zeroed_pages(table, start, start + length, permissions) {
+        start
+    } else {
+        MEMORY_ERROR
+    }
+}
+
+/// Unmaps and frees the pages of the range, pages in it that are not mapped are skipped. Returns
+/// 0 or [MEMORY_ERROR] for ranges outside of the mappings.
+pub fn unmap_anonymous(table: &mut PageTable, address: u64, length: u64) -> u64 {
+    if length == 0 || length > USER_ADDRESS_LIMIT || !is_mmap_range(address, page_align(length)) {
+        return MEMORY_ERROR;
+    }
+
+    unmap_pages(table, address, address + page_align(length));
+
+    0
+}
+
+fn page_align(address: u64) -> u64 {
+    align(address as usize, PAGE_SIZE) as u64
+}
+
+/// Whether the page aligned range lies within the part of the address space used by mmap
+fn is_mmap_range(address: u64, length: u64) -> bool {
+    address.is_multiple_of(PAGE_SIZE as u64)
+        && address >= MMAP_START
+        && address
+            .checked_add(length)
+            .is_some_and(|end| end <= USER_ADDRESS_LIMIT)
+}
+
+/// Whether the page allocator has enough free pages for the length. Checked before any range is
+/// searched, so that requests that cannot succeed are refused right away.
+fn fits_in_memory(length: u64) -> bool {
+    let free_pages = PAGE_ALLOCATOR
+        .lock()
+        .as_ref()
+        .map_or(0, |allocator| allocator.stats().free_pages);
+
+    length.div_ceil(PAGE_SIZE as u64) <= free_pages as u64
+}
+
+/// Finds the lowest range of unmapped pages of the length from [MMAP_START]
+fn find_free_range(table: &PageTable, length: u64) -> Option<u64> {
+    let mut start = MMAP_START;
+
+    loop {
+        let end = start
+            .checked_add(length)
+            .filter(|&end| end <= USER_ADDRESS_LIMIT)?;
+
+        match table.first_mapped_page(start, end) {
+            Some(mapped) => start = mapped + PAGE_SIZE as u64,
+            None => return Some(start),
+        }
+    }
+}
+
+/// Maps zeroed pages from start to end. Fails without mapping anything if a page in the range is
+/// already mapped or the memory runs out.
+fn map_zeroed_pages(
+    table: &mut PageTable,
+    start: u64,
+    end: u64,
+    permissions: PagePermissions,
+) -> bool {
+    if table.first_mapped_page(start, end).is_some() {
+        return false;
+    }
+
+    for page in (start..end).step_by(PAGE_SIZE) {
+        let page_ref = match PLATFORM.allocate_pages(0) {
+            Some(page_ref) => page_ref,
+            None => {
+                unmap_pages(table, start, page);
+                return false;
+            }
+        };
+
+        unsafe {
+            (*page_ref.page).fill(0);
+        }
+
+        // The tables for the page may not fit into the remaining memory either
+        if !table.map_user_address(page, page_ref.page as u64, permissions) {
+            PLATFORM.free_page(page_ref.page as usize);
+            unmap_pages(table, start, page);
+            return false;
+        }
+    }
+
+    // Pages may have been mapped over translations of unmapped pages that were still cached
+    mmu::invalidate_tlb();
+
+    true
+}
+
+/// Unmaps the pages from start to end and frees them once no core can reach them anymore
+fn unmap_pages(table: &mut PageTable, start: u64, end: u64) {
+    let mut pages = Vec::new();
+    let mut address = start;
+
+    while let Some(page) = table.first_mapped_page(address, end) {
+        pages.extend(table.unmap_user_address(page));
+        address = page + PAGE_SIZE as u64;
+    }
+
+    mmu::invalidate_tlb();
+
+    for physical_address in pages {
+        PLATFORM.free_page((physical_address | KERNEL_ADDRESS_START) as usize);
+    }
+}
@@ -0,0 +1,81 @@
+//! Queues of threads that are blocked until another thread wakes them
+//!
+//! A blocked thread is in exactly one queue and on no run queue, so it costs no CPU time until it
+//! is woken. Queues exist only while threads are waiting in them.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+
+use super::thread::{Thread, ThreadID};
+
+/// Returned by the futex wait syscall when the thread was woken
+pub const FUTEX_WOKEN: u64 = 0;
+/// Returned by the futex wait syscall when the word did not hold the expected value
+pub const FUTEX_MISMATCH: u64 = 1;
+/// Returned by the futex syscalls for null or misaligned words
+pub const FUTEX_ERROR: u64 = u64::MAX;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WaitQueueID {
+    /// Threads joining the thread with the id
+    Join(ThreadID),
+    /// Threads waiting on a futex word. Kernel addresses are shared by all threads, for user
+    /// addresses the table is the ttbr of the user table the word is mapped in.
+    Futex { table: usize, address: usize },
+    /// Threads waiting to read from the kernel object at the address
+    ObjectReaders(usize),
+    /// Threads waiting to write to the kernel object at the address
+    ObjectWriters(usize),
+}
+
+pub struct WaitQueues<'a> {
+    queues: BTreeMap<WaitQueueID, VecDeque<Arc<Thread<'a>>>>,
+}
+
+impl<'a> WaitQueues<'a> {
+    pub fn new() -> Self {
+        Self {
+            queues: BTreeMap::new(),
+        }
+    }
+
+    /// Queues the thread behind the threads that already wait in the queue
+    pub fn push(&mut self, id: WaitQueueID, thread: Arc<Thread<'a>>) {
+        self.queues.entry(id).or_default().push_back(thread);
+    }
+
+    /// Takes up to count threads from the front of the queue
+    pub fn wake(&mut self, id: WaitQueueID, count: usize) -> VecDeque<Arc<Thread<'a>>> {
+        let queue = match self.queues.get_mut(&id) {
+            Some(queue) => queue,
+            None => return VecDeque::new(),
+        };
+
+        if count >= queue.len() {
+            return self.queues.remove(&id).unwrap_or_default();
+        }
+
+        queue.drain(..count).collect()
+    }
+
+    /// Removes the thread from the queue, returning whether it was queued
+    pub fn remove(&mut self, id: WaitQueueID, thread: &Arc<Thread<'a>>) -> bool {
+        let queue = match self.queues.get_mut(&id) {
+            Some(queue) => queue,
+            None => return false,
+        };
+
+        let index = match queue.iter().position(|queued| Arc::ptr_eq(queued, thread)) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        queue.remove(index);
+
+        if queue.is_empty() {
+            self.queues.remove(&id);
+        }
+
+        true
+    }
+}
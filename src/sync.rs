@@ -4,6 +4,8 @@ use core::{
     ops::{Deref, DerefMut}
 };
 
+/// A lock that spins until it is free. Interrupts are left alone, so it must not be taken in an
+/// interrupt handler if the interrupted code might hold it.
 pub struct SpinMutex<T> {
     lock: AtomicBool,
     data: UnsafeCell<T>
@@ -19,9 +21,13 @@ impl<'a, T> SpinMutex<T> {
     }
 
     pub fn lock(&'a self) -> SpinMutexGuard<'a, T> {
-
-        //TODO: implement lock on rpi
-        //while self.lock.compare_exchange_weak(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {}
+        while self
+            .lock
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
 
         SpinMutexGuard {
             lock: &self.lock,
@@ -61,7 +67,7 @@ impl<'a, T> DerefMut for SpinMutexGuard<'a, T> {
 impl<'a, T> Drop for SpinMutexGuard<'a, T> {
     /// The dropping of the MutexGuard will release the lock it was created from.
     fn drop(&mut self) {
-        //self.lock.store(false, Ordering::Release);
+        self.lock.store(false, Ordering::Release);
     }
 }
 
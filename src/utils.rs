@@ -8,4 +8,5 @@ pub mod bitfield;
 
 pub mod fat_name;
 
-pub mod bit_array;
\ No newline at end of file
+pub mod bit_array;
+pub mod demangle;
@@ -0,0 +1,120 @@
+use alloc::string::String;
+
+/// Escapes used by the legacy Rust mangling scheme inside of path components
+const ESCAPES: [(&str, &str); 18] = [
+    ("$SP$", "@"),
+    ("$BP$", "*"),
+    ("$RF$", "&"),
+    ("$LT$", "<"),
+    ("$GT$", ">"),
+    ("$LP$", "("),
+    ("$RP$", ")"),
+    ("$C$", ","),
+    ("$u20$", " "),
+    ("$u22$", "\""),
+    ("$u27$", "'"),
+    ("$u2b$", "+"),
+    ("$u3b$", ";"),
+    ("$u5b$", "["),
+    ("$u5d$", "]"),
+    ("$u7b$", "{"),
+    ("$u7d$", "}"),
+    ("$u7e$", "~"),
+];
+
+/// Demangles a symbol in the legacy Rust mangling scheme (_ZN...E) and drops the trailing hash.
+/// Any other name is returned unchanged.
+pub fn demangle(name: &str) -> String {
+    match demangle_legacy(name) {
+        Some(demangled) => demangled,
+        None => String::from(name),
+    }
+}
+
+fn demangle_legacy(name: &str) -> Option<String> {
+    let mut rest = name.strip_prefix("_ZN")?.strip_suffix('E')?;
+    let mut demangled = String::new();
+
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let length: usize = rest[..digits].parse().ok()?;
+        let component = rest.get(digits..digits + length)?;
+
+        rest = &rest[digits + length..];
+
+        let is_hash = rest.is_empty()
+            && component.len() == 17
+            && component.starts_with('h')
+            && component[1..].chars().all(|c| c.is_ascii_hexdigit());
+
+        if is_hash {
+            break;
+        }
+
+        if !demangled.is_empty() {
+            demangled.push_str("::");
+        }
+
+        // Components that would start with an escape are prefixed with an underscore
+        let component = match component.strip_prefix('_') {
+            Some(escaped) if escaped.starts_with('$') => escaped,
+            _ => component,
+        };
+
+        push_component(&mut demangled, component);
+    }
+
+    Some(demangled)
+}
+
+fn push_component(demangled: &mut String, mut component: &str) {
+    while !component.is_empty() {
+        if let Some(rest) = component.strip_prefix("..") {
+            demangled.push_str("::");
+            component = rest;
+            continue;
+        }
+
+        if let Some((escape, replacement)) = ESCAPES
+            .iter()
+            .find(|(escape, _)| component.starts_with(escape))
+        {
+            demangled.push_str(replacement);
+            component = &component[escape.len()..];
+            continue;
+        }
+
+        let c = component.chars().next().unwrap();
+
+        demangled.push(c);
+        component = &component[c.len_utf8()..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::demangle;
+
+    #[test]
+    fn test_function() {
+        assert_eq!(
+            demangle("_ZN8graph_os8platform6raspi36kernel6Kernel14handle_syscall17ha26e0b1f6981836eE"),
+            "graph_os::platform::raspi3::kernel::Kernel::handle_syscall"
+        );
+    }
+
+    #[test]
+    fn test_trait_impl() {
+        assert_eq!(
+            demangle("_ZN67_$LT$graph_os..elf..ProgramHeader$u20$as$u20$core..clone..Clone$GT$5clone17h0123456789abcdefE"),
+            "<graph_os::elf::ProgramHeader as core::clone::Clone>::clone"
+        );
+    }
+
+    #[test]
+    fn test_unmangled() {
+        assert_eq!(demangle("_start"), "_start");
+        assert_eq!(demangle("_ZN3fooE"), "foo");
+        assert_eq!(demangle("_ZN99fooE"), "_ZN99fooE");
+    }
+}